@@ -0,0 +1,253 @@
+use super::models::{EncryptionType, PlaybackManifest};
+use anyhow::{anyhow, Error};
+use roxmltree::{Document, Node};
+use std::str::FromStr;
+
+/// A resolved `application/dash+xml` playback manifest.
+///
+/// TIDAL returns a single period with a single audio representation, whose
+/// `SegmentTemplate`/`SegmentTimeline` is expanded into an ordered list of segment URLs.
+#[derive(Debug)]
+pub struct DashManifest {
+    pub mime_type: String,
+    pub codecs: String,
+    pub init_url: String,
+    pub segment_urls: Vec<String>,
+}
+
+impl DashManifest {
+    pub fn parse(xml: &str) -> Result<Self, Error> {
+        let doc = Document::parse(xml)?;
+        // prefer the representation with the highest bandwidth if multiple are offered
+        let representation = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("Representation"))
+            .max_by_key(|n| parse_attr::<u64>(n, "bandwidth").ok().flatten())
+            .ok_or_else(|| anyhow!("DASH manifest has no Representation"))?;
+        let adaptation_set = representation
+            .parent_element()
+            .filter(|n| n.has_tag_name("AdaptationSet"));
+
+        let inherited = |name: &str| -> Option<String> {
+            representation
+                .attribute(name)
+                .or_else(|| adaptation_set.and_then(|a| a.attribute(name)))
+                .map(str::to_string)
+        };
+        let mime_type =
+            inherited("mimeType").ok_or_else(|| anyhow!("DASH manifest has no mimeType"))?;
+        let codecs = inherited("codecs").unwrap_or_default();
+
+        let template = child(representation, "SegmentTemplate")
+            .or_else(|| adaptation_set.and_then(|a| child(a, "SegmentTemplate")))
+            .ok_or_else(|| anyhow!("DASH manifest has no SegmentTemplate"))?;
+        let media = template
+            .attribute("media")
+            .ok_or_else(|| anyhow!("SegmentTemplate has no media attribute"))?;
+        let init = template
+            .attribute("initialization")
+            .ok_or_else(|| anyhow!("SegmentTemplate has no initialization attribute"))?;
+        let start_number = parse_attr::<u64>(&template, "startNumber")?.unwrap_or(1);
+
+        let vars = TemplateVars {
+            representation_id: representation.attribute("id").unwrap_or_default(),
+            bandwidth: representation.attribute("bandwidth").unwrap_or_default(),
+            number: start_number,
+            time: 0,
+        };
+        let init_url = fill_template(init, &vars)?;
+
+        let timeline = child(template, "SegmentTimeline")
+            .ok_or_else(|| anyhow!("SegmentTemplate has no SegmentTimeline"))?;
+        let mut segment_urls = Vec::new();
+        let mut number = start_number;
+        let mut time = 0;
+        for s in timeline.children().filter(|n| n.has_tag_name("S")) {
+            let duration = parse_attr::<u64>(&s, "d")?
+                .ok_or_else(|| anyhow!("SegmentTimeline entry has no duration"))?;
+            let repeat = parse_attr::<i64>(&s, "r")?.unwrap_or(0);
+            if repeat < 0 {
+                return Err(anyhow!(
+                    "Open ended SegmentTimeline repeats are not supported"
+                ));
+            }
+            if let Some(t) = parse_attr::<u64>(&s, "t")? {
+                time = t;
+            }
+            for _ in 0..=repeat {
+                let segment = TemplateVars {
+                    number,
+                    time,
+                    ..vars
+                };
+                segment_urls.push(fill_template(media, &segment)?);
+                number += 1;
+                time += duration;
+            }
+        }
+
+        Ok(Self {
+            mime_type,
+            codecs,
+            init_url,
+            segment_urls,
+        })
+    }
+}
+
+impl FromStr for DashManifest {
+    type Err = Error;
+    fn from_str(input: &str) -> Result<DashManifest, Self::Err> {
+        let decode = base64::decode(input)?;
+        let xml = String::from_utf8(decode)?;
+        DashManifest::parse(&xml)
+    }
+}
+
+impl From<DashManifest> for PlaybackManifest {
+    fn from(dash: DashManifest) -> Self {
+        let mut urls = Vec::with_capacity(dash.segment_urls.len() + 1);
+        urls.push(dash.init_url);
+        urls.extend(dash.segment_urls);
+        Self {
+            mime_type: dash.mime_type,
+            codecs: dash.codecs,
+            encryption_type: EncryptionType::None,
+            key_id: None,
+            urls,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct TemplateVars<'a> {
+    representation_id: &'a str,
+    bandwidth: &'a str,
+    number: u64,
+    time: u64,
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+fn parse_attr<T: FromStr>(node: &Node, name: &str) -> Result<Option<T>, Error> {
+    node.attribute(name)
+        .map(|v| {
+            v.parse::<T>()
+                .map_err(|_| anyhow!("Invalid value for {name} in DASH manifest: {v}"))
+        })
+        .transpose()
+}
+
+// Substitutes the $Identifier$ and $Identifier%0Nd$ placeholders of a SegmentTemplate url
+fn fill_template(template: &str, vars: &TemplateVars) -> Result<String, Error> {
+    let mut parts = template.split('$');
+    let mut url = parts.next().unwrap_or_default().to_string();
+    let mut in_ident = false;
+    for part in parts {
+        in_ident = !in_ident;
+        if !in_ident {
+            url.push_str(part);
+            continue;
+        }
+        if part.is_empty() {
+            // $$ is an escaped dollar sign
+            url.push('$');
+            continue;
+        }
+        let (ident, width) = match part.split_once("%0") {
+            Some((ident, fmt)) => {
+                let width = fmt
+                    .strip_suffix('d')
+                    .and_then(|w| w.parse::<usize>().ok())
+                    .ok_or_else(|| anyhow!("Invalid format in SegmentTemplate: {part}"))?;
+                (ident, width)
+            }
+            None => (part, 0),
+        };
+        let value = match ident {
+            "RepresentationID" => vars.representation_id.to_string(),
+            "Bandwidth" => vars.bandwidth.to_string(),
+            "Number" => vars.number.to_string(),
+            "Time" => vars.time.to_string(),
+            _ => return Err(anyhow!("Unknown SegmentTemplate identifier: {ident}")),
+        };
+        url.push_str(&format!("{value:0>width$}"));
+    }
+    if !in_ident {
+        Ok(url)
+    } else {
+        Err(anyhow!(
+            "Unterminated identifier in SegmentTemplate: {template}"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MPD: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static">
+  <Period id="0">
+    <AdaptationSet id="0" contentType="audio" mimeType="audio/mp4">
+      <SegmentTemplate timescale="44100" initialization="https://cdn.test/$RepresentationID$/init.mp4"
+          media="https://cdn.test/$RepresentationID$/$Number%05d$-$Time$.mp4?b=$Bandwidth$&amp;x=$$" startNumber="3">
+        <SegmentTimeline>
+          <S t="0" d="100" r="2"/>
+          <S d="50"/>
+          <S t="1000" d="10" r="1"/>
+        </SegmentTimeline>
+      </SegmentTemplate>
+      <Representation id="low" codecs="mp4a.40.2" bandwidth="96000"/>
+      <Representation id="FLAC" codecs="flac" bandwidth="1000000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+    #[test]
+    fn expands_the_segment_timeline() {
+        let dash = DashManifest::parse(MPD).unwrap();
+        assert_eq!(dash.mime_type, "audio/mp4");
+        assert_eq!(dash.codecs, "flac");
+        assert_eq!(dash.init_url, "https://cdn.test/FLAC/init.mp4");
+        // r repeats a segment r more times, t resets the time, numbers count up from
+        // startNumber across entries
+        let segments: Vec<String> = [(3, 0), (4, 100), (5, 200), (6, 300), (7, 1000), (8, 1010)]
+            .iter()
+            .map(|(n, t)| format!("https://cdn.test/FLAC/{n:05}-{t}.mp4?b=1000000&x=$"))
+            .collect();
+        assert_eq!(dash.segment_urls, segments);
+
+        let manifest =
+            PlaybackManifest::from(DashManifest::from_str(&base64::encode(MPD)).unwrap());
+        assert_eq!(manifest.urls.len(), 7);
+        assert_eq!(manifest.urls[0], "https://cdn.test/FLAC/init.mp4");
+        assert_eq!(manifest.urls[1..], segments);
+    }
+
+    #[test]
+    fn fills_templates() {
+        let vars = TemplateVars {
+            representation_id: "r1",
+            bandwidth: "320",
+            number: 42,
+            time: 7,
+        };
+        let fill = |template| fill_template(template, &vars);
+        assert_eq!(fill("$Number%05d$.mp4").unwrap(), "00042.mp4");
+        assert_eq!(fill("$Number%01d$").unwrap(), "42");
+        assert_eq!(fill("$RepresentationID$/$Time$").unwrap(), "r1/7");
+        assert_eq!(fill("a$$b").unwrap(), "a$b");
+        assert!(fill("$Unknown$").is_err());
+        assert!(fill("$Number%5d$").is_err());
+        assert!(fill("$Number").is_err());
+    }
+
+    #[test]
+    fn rejects_open_ended_repeats() {
+        let mpd = MPD.replace(r#"r="2""#, r#"r="-1""#);
+        assert!(DashManifest::parse(&mpd).is_err());
+    }
+}
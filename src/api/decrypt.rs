@@ -0,0 +1,145 @@
+use super::models::{EncryptionType, PlaybackManifest};
+use aes::cipher::{
+    block_padding::NoPadding, BlockDecryptMut, KeyIvInit, StreamCipher, StreamCipherSeek,
+};
+use aes::{Aes128, Aes256};
+use anyhow::{anyhow, Error};
+
+type MasterKeyDecryptor = cbc::Decryptor<Aes256>;
+type StreamCipherCtr = ctr::Ctr64BE<Aes128>;
+
+// Shared key the TIDAL clients use to wrap the per track key in `key_id`
+const MASTER_KEY: &str = "UIlTTEMmmLfGowo/UC60x2H45W6MdGgTRfo/umg4754=";
+
+/// Decrypts an `OLD_AES` encrypted stream chunk by chunk as it is downloaded.
+pub struct StreamDecryptor(StreamCipherCtr);
+
+impl StreamDecryptor {
+    /// Derives the stream key from the manifest's `key_id`.
+    ///
+    /// The first 16 bytes of the security token are the IV to decrypt the rest with the
+    /// master key using AES-256-CBC, which yields the 16 byte AES-128 key followed by
+    /// the 8 byte nonce of the counter used to decrypt the stream.
+    pub fn new(key_id: &str) -> Result<Self, Error> {
+        let master_key = base64::decode(MASTER_KEY)?;
+        let security_token = base64::decode(key_id)?;
+        if security_token.len() < 48 {
+            return Err(anyhow!("Encryption key id is too short"));
+        }
+        let (iv, encrypted) = security_token.split_at(16);
+        let mut decrypted = encrypted.to_vec();
+        MasterKeyDecryptor::new_from_slices(&master_key, iv)
+            .map_err(|e| anyhow!("Invalid master key: {e}"))?
+            .decrypt_padded_mut::<NoPadding>(&mut decrypted)
+            .map_err(|e| anyhow!("Unable to decrypt key id: {e}"))?;
+
+        let (key, nonce) = (&decrypted[..16], &decrypted[16..24]);
+        let mut counter = [0u8; 16];
+        counter[..8].copy_from_slice(nonce);
+        let cipher = StreamCipherCtr::new_from_slices(key, &counter)
+            .map_err(|e| anyhow!("Invalid stream key: {e}"))?;
+        Ok(Self(cipher))
+    }
+
+    /// Decrypts the next chunk of the stream in place.
+    pub fn apply(&mut self, chunk: &mut [u8]) {
+        self.0.apply_keystream(chunk);
+    }
+
+    /// Moves the keystream to a byte offset, used when resuming a partial download.
+    pub fn seek(&mut self, offset: u64) {
+        self.0.seek(offset);
+    }
+}
+
+impl PlaybackManifest {
+    pub fn get_decryptor(&self) -> Result<Option<StreamDecryptor>, Error> {
+        match self.encryption_type {
+            EncryptionType::None => Ok(None),
+            EncryptionType::OldAes => {
+                let key_id = self
+                    .key_id
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Encrypted manifest is missing its key id"))?;
+                Ok(Some(StreamDecryptor::new(key_id)?))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::{BlockEncrypt, BlockEncryptMut, KeyInit};
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+    const NONCE: [u8; 8] = *b"noncenon";
+
+    // wraps KEY and NONCE into a key id the same way the TIDAL API does
+    fn key_id() -> String {
+        let master_key = base64::decode(MASTER_KEY).unwrap();
+        let iv = [7u8; 16];
+        let mut wrapped = [0u8; 32];
+        wrapped[..16].copy_from_slice(&KEY);
+        wrapped[16..24].copy_from_slice(&NONCE);
+        cbc::Encryptor::<Aes256>::new_from_slices(&master_key, &iv)
+            .unwrap()
+            .encrypt_padded_mut::<NoPadding>(&mut wrapped, 32)
+            .unwrap();
+        base64::encode([&iv[..], &wrapped[..]].concat())
+    }
+
+    // AES-128-CTR with the nonce in the upper and the block counter in the lower 8 bytes
+    fn encrypt(plaintext: &[u8]) -> Vec<u8> {
+        let cipher = Aes128::new(&KEY.into());
+        plaintext
+            .chunks(16)
+            .enumerate()
+            .flat_map(|(i, block)| {
+                let mut counter = [0u8; 16];
+                counter[..8].copy_from_slice(&NONCE);
+                counter[8..].copy_from_slice(&(i as u64).to_be_bytes());
+                let mut keystream = counter.into();
+                cipher.encrypt_block(&mut keystream);
+                block
+                    .iter()
+                    .zip(keystream)
+                    .map(|(b, k)| b ^ k)
+                    .collect::<Vec<u8>>()
+            })
+            .collect()
+    }
+
+    fn plaintext() -> Vec<u8> {
+        (0..1000u32).map(|i| (i * 7 % 256) as u8).collect()
+    }
+
+    #[test]
+    fn decrypts_a_stream() {
+        let plaintext = plaintext();
+        let mut stream = encrypt(&plaintext);
+        let mut decryptor = StreamDecryptor::new(&key_id()).unwrap();
+        // chunks as they come off the network don't line up with the cipher blocks
+        for chunk in stream.chunks_mut(333) {
+            decryptor.apply(chunk);
+        }
+        assert_eq!(stream, plaintext);
+    }
+
+    #[test]
+    fn decrypts_from_an_offset() {
+        let plaintext = plaintext();
+        let offset = 517;
+        let mut rest = encrypt(&plaintext)[offset..].to_vec();
+        let mut decryptor = StreamDecryptor::new(&key_id()).unwrap();
+        decryptor.seek(offset as u64);
+        decryptor.apply(&mut rest);
+        assert_eq!(rest, &plaintext[offset..]);
+    }
+
+    #[test]
+    fn rejects_a_short_key_id() {
+        let key_id = base64::encode([0u8; 47]);
+        assert!(StreamDecryptor::new(&key_id).is_err());
+    }
+}
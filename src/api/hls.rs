@@ -0,0 +1,253 @@
+use super::models::VideoResolution;
+use anyhow::{anyhow, Error};
+use reqwest::Url;
+
+/// A stream of an HLS master playlist, one per resolution of a video.
+#[derive(Debug)]
+pub struct HlsVariant {
+    pub url: String,
+    pub bandwidth: u64,
+    pub height: Option<u32>,
+}
+
+/// Lists the `#EXT-X-STREAM-INF` streams of a master playlist, with URLs resolved against `base`.
+pub fn parse_master_playlist(playlist: &str, base: &str) -> Result<Vec<HlsVariant>, Error> {
+    let base = Url::parse(base)?;
+    let mut variants = Vec::new();
+    let mut lines = playlist.lines().map(str::trim).filter(|l| !l.is_empty());
+    while let Some(line) = lines.next() {
+        let attributes = match line.strip_prefix("#EXT-X-STREAM-INF:") {
+            Some(attributes) => attributes,
+            None => continue,
+        };
+        // the stream's URI is the next line that isn't a tag
+        let uri = lines
+            .by_ref()
+            .find(|l| !l.starts_with('#'))
+            .ok_or_else(|| anyhow!("HLS stream has no URI"))?;
+        let mut variant = HlsVariant {
+            url: base.join(uri)?.to_string(),
+            bandwidth: 0,
+            height: None,
+        };
+        for (name, value) in parse_attributes(attributes) {
+            match name {
+                "BANDWIDTH" => variant.bandwidth = value.parse().unwrap_or_default(),
+                "RESOLUTION" => {
+                    variant.height = value.split_once('x').and_then(|(_, h)| h.parse().ok())
+                }
+                _ => {}
+            }
+        }
+        variants.push(variant);
+    }
+    if variants.is_empty() {
+        return Err(anyhow!("HLS master playlist has no streams"));
+    }
+    Ok(variants)
+}
+
+/// Picks the highest quality stream that is no taller than `resolution`, or the
+/// smallest stream if they are all taller.
+pub fn select_variant(variants: &[HlsVariant], resolution: VideoResolution) -> Option<&HlsVariant> {
+    let max_height = resolution.height();
+    let key = |v: &&HlsVariant| (v.height.unwrap_or_default(), v.bandwidth);
+    variants
+        .iter()
+        .filter(|v| v.height.unwrap_or_default() <= max_height)
+        .max_by_key(key)
+        .or_else(|| variants.iter().min_by_key(key))
+}
+
+/// The segments of an HLS media playlist
+#[derive(Debug)]
+pub struct HlsStream {
+    pub segment_urls: Vec<String>,
+    /// fragmented MP4 segments following an initialization section, instead of MPEG-TS
+    pub fragmented: bool,
+}
+
+impl HlsStream {
+    pub fn get_file_extension(&self) -> &str {
+        match self.fragmented {
+            true => "mp4",
+            false => "ts",
+        }
+    }
+}
+
+/// Lists the segment URLs of a media playlist in playback order, starting with the
+/// `#EXT-X-MAP` initialization section if the stream has one.
+pub fn parse_media_playlist(playlist: &str, base: &str) -> Result<HlsStream, Error> {
+    let base = Url::parse(base)?;
+    let mut fragmented = false;
+    let mut segments = Vec::new();
+    for line in playlist.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(attributes) = line.strip_prefix("#EXT-X-KEY:") {
+            let method = parse_attributes(attributes).find(|(name, _)| *name == "METHOD");
+            if !matches!(method, None | Some((_, "NONE"))) {
+                return Err(anyhow!("Encrypted HLS streams are not supported"));
+            }
+        } else if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+            let uri = parse_attributes(attributes)
+                .find(|(name, _)| *name == "URI")
+                .ok_or_else(|| anyhow!("HLS initialization section has no URI"))?
+                .1;
+            segments.push(base.join(uri)?.to_string());
+            fragmented = true;
+        } else if !line.starts_with('#') {
+            segments.push(base.join(line)?.to_string());
+        }
+    }
+    if segments.is_empty() {
+        return Err(anyhow!("HLS media playlist has no segments"));
+    }
+    Ok(HlsStream {
+        segment_urls: segments,
+        fragmented,
+    })
+}
+
+// Splits an attribute list of NAME=VALUE pairs, where quoted values may contain commas
+fn parse_attributes(list: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        let (name, value) = rest.split_once('=')?;
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((v, next)) => (v, next),
+                None => (quoted, ""),
+            },
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        rest = next.trim_start_matches(',').trim_start();
+        Some((name.trim(), value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "https://video.test/path/master.m3u8?token=1";
+
+    const MASTER: &str = "#EXTM3U
+#EXT-X-VERSION:3
+
+#EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS=\"avc1.4d401f,mp4a.40.2\",RESOLUTION=640x360
+360/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=2500000,CODECS=\"avc1.4d401f,mp4a.40.2\",RESOLUTION=1280x720
+#EXT-X-SOMETHING-ELSE
+720/index.m3u8
+#EXT-X-STREAM-INF:RESOLUTION=1280x720,BANDWIDTH=2000000
+720-low/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080
+https://other.test/1080/index.m3u8
+";
+
+    fn heights(variant: Option<&HlsVariant>) -> (Option<u32>, u64) {
+        let variant = variant.unwrap();
+        (variant.height, variant.bandwidth)
+    }
+
+    #[test]
+    fn parses_master_playlists() {
+        let variants = parse_master_playlist(MASTER, BASE).unwrap();
+        let parsed: Vec<(&str, u64, Option<u32>)> = variants
+            .iter()
+            .map(|v| (v.url.as_str(), v.bandwidth, v.height))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                ("https://video.test/path/360/index.m3u8", 800000, Some(360)),
+                ("https://video.test/path/720/index.m3u8", 2500000, Some(720)),
+                (
+                    "https://video.test/path/720-low/index.m3u8",
+                    2000000,
+                    Some(720)
+                ),
+                ("https://other.test/1080/index.m3u8", 5000000, Some(1080)),
+            ]
+        );
+        assert!(parse_master_playlist("#EXTM3U\n", BASE).is_err());
+        assert!(parse_master_playlist("#EXT-X-STREAM-INF:BANDWIDTH=1\n", BASE).is_err());
+    }
+
+    #[test]
+    fn selects_the_best_variant_within_the_resolution() {
+        let variants = parse_master_playlist(MASTER, BASE).unwrap();
+        let select = |resolution| heights(select_variant(&variants, resolution));
+        assert_eq!(select(VideoResolution::R1080), (Some(1080), 5000000));
+        // the higher bandwidth of two streams of the same height
+        assert_eq!(select(VideoResolution::R720), (Some(720), 2500000));
+        assert_eq!(select(VideoResolution::R480), (Some(360), 800000));
+        assert_eq!(select(VideoResolution::R360), (Some(360), 800000));
+
+        // the smallest stream when every stream is too tall
+        let tall = &variants[1..];
+        assert_eq!(
+            heights(select_variant(tall, VideoResolution::R360)),
+            (Some(720), 2000000)
+        );
+    }
+
+    #[test]
+    fn parses_media_playlists() {
+        let playlist = "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-KEY:METHOD=NONE
+#EXTINF:4.0,
+seg-1.ts
+#EXTINF:4.0,
+seg-2.ts
+#EXT-X-ENDLIST
+";
+        let stream = parse_media_playlist(playlist, BASE).unwrap();
+        assert_eq!(
+            stream.segment_urls,
+            [
+                "https://video.test/path/seg-1.ts",
+                "https://video.test/path/seg-2.ts"
+            ]
+        );
+        assert!(!stream.fragmented);
+        assert_eq!(stream.get_file_extension(), "ts");
+    }
+
+    #[test]
+    fn puts_the_initialization_section_first() {
+        let playlist = "#EXTM3U
+#EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"
+#EXTINF:4.0,
+/abs/seg-1.m4s
+";
+        let stream = parse_media_playlist(playlist, BASE).unwrap();
+        assert_eq!(
+            stream.segment_urls,
+            [
+                "https://video.test/path/init.mp4",
+                "https://video.test/abs/seg-1.m4s"
+            ]
+        );
+        assert!(stream.fragmented);
+        assert_eq!(stream.get_file_extension(), "mp4");
+    }
+
+    #[test]
+    fn rejects_encrypted_and_empty_media_playlists() {
+        let encrypted = "#EXT-X-KEY:METHOD=AES-128,URI=\"key\"\nseg-1.ts\n";
+        assert!(parse_media_playlist(encrypted, BASE).is_err());
+        assert!(parse_media_playlist("#EXTM3U\n#EXT-X-ENDLIST\n", BASE).is_err());
+    }
+
+    #[test]
+    fn splits_attributes_with_quoted_commas() {
+        let attributes: Vec<(&str, &str)> =
+            parse_attributes("BANDWIDTH=1, CODECS=\"a,b\",RESOLUTION=1x2").collect();
+        assert_eq!(
+            attributes,
+            [("BANDWIDTH", "1"), ("CODECS", "a,b"), ("RESOLUTION", "1x2")]
+        );
+    }
+}
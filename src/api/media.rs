@@ -1,16 +1,31 @@
-use super::{models::*, ApiClient};
+use super::hls::{parse_master_playlist, parse_media_playlist, select_variant, HlsStream};
+use super::{dash::DashManifest, memo::Memo, models::*, ApiClient};
 use anyhow::anyhow;
 use anyhow::Error;
+use log::debug;
+use serde::de::DeserializeOwned;
 use std::ops::Deref;
 use std::str::FromStr;
 use std::sync::Arc;
 use tokio::try_join;
 
-pub struct MediaClient(Arc<ApiClient>);
+pub struct MediaClient {
+    client: Arc<ApiClient>,
+    // every track of an album looks up the same album, artist and cover
+    albums: Memo<usize, Album>,
+    artists: Memo<String, Artist>,
+    // covers are only shared while being downloaded, their bytes are not kept for the run
+    covers: Memo<(String, CoverResolution), Cover>,
+}
 
 impl MediaClient {
     pub fn new(client: Arc<ApiClient>) -> Self {
-        Self(client)
+        Self {
+            client,
+            albums: Memo::default(),
+            artists: Memo::default(),
+            covers: Memo::default(),
+        }
     }
 }
 
@@ -18,7 +33,7 @@ impl Deref for MediaClient {
     type Target = ApiClient;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.client
     }
 }
 
@@ -29,18 +44,30 @@ impl MediaClient {
     }
 
     pub async fn get_album(&self, id: usize) -> Result<Album, Error> {
-        let url = format!("{}/albums/{}", &self.api_base, id);
-        self.get::<Album>(&url, None).await
+        self.albums
+            .get_or_fetch(id, || async {
+                let url = format!("{}/albums/{}", &self.api_base, id);
+                self.get::<Album>(&url, None).await
+            })
+            .await
     }
 
     pub async fn get_artist(&self, id: &str) -> Result<Artist, Error> {
-        let url = format!("https://api.tidal.com/v1/artists/{id}");
-        self.get::<Artist>(&url, None).await
+        self.artists
+            .get_or_fetch(id.to_string(), || async {
+                let url = format!("https://api.tidal.com/v1/artists/{id}");
+                self.get::<Artist>(&url, None).await
+            })
+            .await
     }
-    pub async fn get_stream_url(&self, id: usize) -> Result<PlaybackManifest, Error> {
+    pub async fn get_stream_url(
+        &self,
+        id: usize,
+        audio_quality: AudioQuality,
+    ) -> Result<PlaybackManifest, Error> {
         let url = format!("{}/tracks/{}/playbackinfopostpaywall", &self.api_base, id);
         let query = &[
-            ("audioquality".to_string(), self.audio_quality.to_string()),
+            ("audioquality".to_string(), audio_quality.to_string()),
             ("playbackmode".to_string(), PlaybackMode::Stream.to_string()),
             (
                 "assetpresentation".to_string(),
@@ -54,15 +81,110 @@ impl MediaClient {
 
         match req.manifest_mime_type.as_str() {
             "application/vnd.tidal.bts" => Ok(PlaybackManifest::from_str(&req.manifest)?),
+            "application/dash+xml" => Ok(DashManifest::from_str(&req.manifest)?.into()),
             _ => Err(Error::msg("Incorrect Mimetype on Response")),
         }
     }
 
-    pub async fn get_artist_albums(&self, id: &str) -> Result<Vec<Album>, Error> {
+    pub async fn get_playlist(&self, uuid: &str) -> Result<Playlist, Error> {
+        let url = format!("{}/playlists/{}", &self.api_base, uuid);
+        self.get::<Playlist>(&url, None).await
+    }
+
+    pub async fn get_mix(&self, id: &str) -> Result<Mix, Error> {
+        // mixes only have a title on the page that shows them
+        let url = "https://api.tidal.com/v1/pages/mix";
+        let query = &[
+            ("mixId".to_string(), id.to_string()),
+            ("deviceType".to_string(), "BROWSER".to_string()),
+        ];
+        let page = self.get::<MixPage>(url, Some(query)).await?;
+        Ok(Mix {
+            id: id.to_string(),
+            title: page.title,
+        })
+    }
+
+    pub async fn get_mix_items(&self, id: &str) -> Result<Vec<ListItem>, Error> {
+        let url = format!("https://api.tidal.com/v1/mixes/{id}/items");
+        self.get_items::<ListItem>(&url, None, None).await
+    }
+
+    pub async fn get_artist_mix(&self, id: &str) -> Result<String, Error> {
+        let url = format!("https://api.tidal.com/v1/artists/{id}/mix");
+        Ok(self.get::<ArtistMix>(&url, None).await?.id)
+    }
+
+    /// Lists a collection of the logged in user's favorites, such as `tracks` or `albums`.
+    pub async fn get_favorites<T>(&self, collection: &str) -> Result<Vec<T>, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let user_id = self
+            .user_id
+            .ok_or_else(|| anyhow!("No user id is stored, login again to download favorites"))?;
+        let url = format!("{}/users/{user_id}/favorites/{collection}", &self.api_base);
+        let items = self.get_items::<FavoriteItem<T>>(&url, None, None).await?;
+        Ok(items.into_iter().map(|i| i.item).collect())
+    }
+
+    pub async fn get_video(&self, id: &str) -> Result<Video, Error> {
+        let url = format!("{}/videos/{}", &self.api_base, id);
+        self.get::<Video>(&url, None).await
+    }
+
+    /// Resolves the HLS stream of a video closest to `resolution`.
+    pub async fn get_video_stream(
+        &self,
+        id: usize,
+        resolution: VideoResolution,
+    ) -> Result<HlsStream, Error> {
+        let url = format!("{}/videos/{}/playbackinfopostpaywall", &self.api_base, id);
+        let query = &[
+            ("videoquality".to_string(), "HIGH".to_string()),
+            ("playbackmode".to_string(), PlaybackMode::Stream.to_string()),
+            (
+                "assetpresentation".to_string(),
+                AssetPresentation::Full.to_string(),
+            ),
+        ];
+        let req = self.get::<VideoPlaybackInfoRes>(&url, Some(query)).await?;
+        if req.manifest_mime_type != "application/vnd.tidal.emu" {
+            return Err(Error::msg("Incorrect Mimetype on Response"));
+        }
+        let manifest = VideoManifest::from_str(&req.manifest)?;
+        let master_url = manifest
+            .urls
+            .first()
+            .ok_or_else(|| anyhow!("Video manifest has no playlist URL"))?;
+
+        let master = self.get_text(master_url).await?;
+        let variants = parse_master_playlist(&master, master_url)?;
+        let variant =
+            select_variant(&variants, resolution).ok_or_else(|| anyhow!("Video has no streams"))?;
+        debug!(
+            "Selected {}p stream of video {id}",
+            variant.height.unwrap_or_default()
+        );
+        let media = self.get_text(&variant.url).await?;
+        parse_media_playlist(&media, &variant.url)
+    }
+
+    async fn get_text(&self, url: &str) -> Result<String, Error> {
+        let req = self.http_client.get(url).send().await?;
+        req.error_for_status_ref()?;
+        Ok(req.text().await?)
+    }
+
+    pub async fn get_artist_albums(
+        &self,
+        id: &str,
+        include_singles: bool,
+    ) -> Result<Vec<Album>, Error> {
         let url = format!("https://api.tidal.com/v1/artists/{id}/albums");
         let mut albums: Vec<Album> = Vec::new();
         let album_req = self.get_items::<Album>(&url, None, None);
-        if self.include_singles {
+        if include_singles {
             let filter = vec![("filter".to_string(), "EPSANDSINGLES".to_string())];
             let singles = self.get_items::<Album>(&url, Some(filter), None);
             //execute the two requests concurrently
@@ -80,21 +202,41 @@ impl MediaClient {
         Ok(albums)
     }
 
-    fn get_cover_url(id: &str, width: usize, height: usize) -> String {
+    fn get_cover_url(id: &str, resolution: CoverResolution) -> String {
+        let size = match resolution {
+            CoverResolution::Origin => resolution.to_string(),
+            _ => format!("{resolution}x{resolution}"),
+        };
         format!(
-            "https://resources.tidal.com/images/{}/{}x{}.jpg",
+            "https://resources.tidal.com/images/{}/{}.jpg",
             id.replace('-', "/"),
-            width,
-            height
+            size
         )
     }
 
-    pub async fn get_cover_data(&self, id: &str) -> Result<Cover, Error> {
+    pub async fn get_cover_data(
+        &self,
+        id: &str,
+        resolution: CoverResolution,
+    ) -> Result<Cover, Error> {
+        self.covers
+            .share_in_flight((id.to_string(), resolution), || {
+                self.fetch_cover_data(id, resolution)
+            })
+            .await
+    }
+
+    async fn fetch_cover_data(
+        &self,
+        id: &str,
+        resolution: CoverResolution,
+    ) -> Result<Cover, Error> {
         let req = self
             .http_client
-            .get(MediaClient::get_cover_url(id, 1280, 1280))
+            .get(MediaClient::get_cover_url(id, resolution))
             .send()
             .await?;
+        req.error_for_status_ref()?;
 
         let content_type = match req.headers().get("Content-Type") {
             Some(val) => val.to_str()?.to_string(),
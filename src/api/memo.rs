@@ -0,0 +1,102 @@
+use anyhow::Error;
+use futures::Future;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// In-process memo of API responses for the duration of a run.
+///
+/// Concurrent lookups of the same key share a single in-flight request,
+/// failed requests are not memoized and will be retried by the next lookup.
+pub struct Memo<K, V>(Mutex<HashMap<K, Arc<OnceCell<V>>>>);
+
+impl<K, V> Default for Memo<K, V> {
+    fn default() -> Self {
+        Self(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<K, V> Memo<K, V>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    pub async fn get_or_fetch<F, Fut>(&self, key: K, fetch: F) -> Result<V, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, Error>>,
+    {
+        let cell = self
+            .0
+            .lock()
+            .expect("Memo lock poisoned")
+            .entry(key)
+            .or_default()
+            .clone();
+        cell.get_or_try_init(fetch).await.cloned()
+    }
+
+    /// Like [`Memo::get_or_fetch`], but the value is only shared with the lookups
+    /// made while it is being fetched and is not kept once the fetch is done.
+    pub async fn share_in_flight<F, Fut>(&self, key: K, fetch: F) -> Result<V, Error>
+    where
+        K: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, Error>>,
+    {
+        let cell = self
+            .0
+            .lock()
+            .expect("Memo lock poisoned")
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let res = cell.get_or_try_init(fetch).await.cloned();
+        let mut map = self.0.lock().expect("Memo lock poisoned");
+        // a later lookup may already have started a new fetch under the same key
+        if map.get(&key).is_some_and(|c| Arc::ptr_eq(c, &cell)) {
+            map.remove(&key);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn keeps_values_of_finished_fetches() {
+        let memo = Memo::default();
+        let fetches = AtomicUsize::new(0);
+        for _ in 0..2 {
+            let v = memo
+                .get_or_fetch(1, || async { Ok(fetches.fetch_add(1, Ordering::SeqCst)) })
+                .await
+                .unwrap();
+            assert_eq!(v, 0);
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn shares_only_fetches_in_flight() {
+        let memo = Memo::default();
+        let fetches = AtomicUsize::new(0);
+        let fetch = || async {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            Ok(fetches.fetch_add(1, Ordering::SeqCst))
+        };
+        let (a, b) = tokio::join!(
+            memo.share_in_flight(1, fetch),
+            memo.share_in_flight(1, fetch)
+        );
+        assert_eq!((a.unwrap(), b.unwrap()), (0, 0));
+        assert!(memo.0.lock().unwrap().is_empty());
+
+        let c = memo.share_in_flight(1, fetch).await.unwrap();
+        assert_eq!(c, 1);
+    }
+}
@@ -1,9 +1,6 @@
 use std::sync::Arc;
 
-use self::{
-    media::MediaClient,
-    models::{AudioQuality, ItemResponse},
-};
+use self::{media::MediaClient, models::ItemResponse};
 use crate::config::Settings;
 use anyhow::Error;
 use http_cache_reqwest::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
@@ -14,9 +11,13 @@ use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use serde::de::DeserializeOwned;
 
 pub mod auth;
+pub mod dash;
+pub mod decrypt;
+pub mod hls;
 pub mod media;
+mod memo;
 pub mod models;
-mod search;
+pub mod search;
 
 use search::SearchClient;
 
@@ -81,8 +82,7 @@ impl TidalClient {
 pub struct ApiClient {
     country_code: (String, String),
     access_token: String,
-    audio_quality: AudioQuality,
-    include_singles: bool,
+    user_id: Option<i64>,
     api_base: String,
     http_client: ClientWithMiddleware,
 }
@@ -101,10 +101,9 @@ impl ApiClient {
                 .login_key
                 .access_token
                 .expect("Access Token is not present in config"),
+            user_id: config.login_key.user_id,
             http_client: build_middleware_client(config.cache_dir),
-            include_singles: config.include_singles,
             api_base: String::from("https://api.tidalhifi.com/v1"),
-            audio_quality: config.audio_quality,
         })
     }
 
@@ -74,6 +74,48 @@ pub struct ItemResponseItem<T> {
     pub item_type: String,
 }
 
+/// An item of an album or playlist, which holds both tracks and videos
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", content = "item", rename_all = "lowercase")]
+pub enum ListItem {
+    Track(Track),
+    Video(Video),
+}
+
+/// An item of the user's favorites, with the time it was added
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FavoriteItem<T> {
+    pub created: Option<String>,
+    pub item: T,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct VideoPlaybackInfoRes {
+    pub video_id: usize,
+    pub video_quality: String,
+    pub manifest_mime_type: String,
+    pub manifest: String,
+}
+
+/// The `application/vnd.tidal.emu` manifest of a video, pointing to an HLS master playlist
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct VideoManifest {
+    pub mime_type: String,
+    pub urls: Vec<String>,
+}
+
+impl FromStr for VideoManifest {
+    type Err = anyhow::Error;
+    fn from_str(input: &str) -> Result<VideoManifest, Self::Err> {
+        let decode = base64::decode(input)?;
+        let json = String::from_utf8(decode)?;
+        let parsed: VideoManifest = serde_json::from_str(&json)?;
+        Ok(parsed)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all(deserialize = "camelCase"))]
 pub struct PlaybackInfoPostPaywallRes {
@@ -84,11 +126,68 @@ pub struct PlaybackInfoPostPaywallRes {
     pub manifest: String,
 }
 
+#[derive(Clone)]
 pub struct Cover {
     pub content_type: String,
     pub data: Vec<u8>,
 }
 
+/// A row of the `tdl search` results table
+#[derive(Tabled)]
+pub struct SearchResult {
+    #[tabled(rename = "ID")]
+    pub id: String,
+    #[tabled(rename = "Name")]
+    pub name: String,
+    #[tabled(rename = "Artist")]
+    pub artist: String,
+    #[tabled(rename = "URL")]
+    pub url: String,
+}
+
+impl SearchResult {
+    fn new(kind: &str, id: String, name: &str, artist: &str) -> Self {
+        Self {
+            url: format!("https://tidal.com/browse/{kind}/{id}"),
+            id,
+            name: name.to_string(),
+            artist: artist.to_string(),
+        }
+    }
+}
+
+impl From<Track> for SearchResult {
+    fn from(t: Track) -> Self {
+        SearchResult::new("track", t.id.to_string(), t.get_name(), t.artist.get_name())
+    }
+}
+
+impl From<Album> for SearchResult {
+    fn from(a: Album) -> Self {
+        let artist = display_option_named(&a.artist);
+        SearchResult::new("album", a.id.to_string(), a.get_name(), &artist)
+    }
+}
+
+impl From<Artist> for SearchResult {
+    fn from(a: Artist) -> Self {
+        SearchResult::new("artist", a.id.to_string(), a.get_name(), "")
+    }
+}
+
+impl From<Video> for SearchResult {
+    fn from(v: Video) -> Self {
+        SearchResult::new("video", v.id.to_string(), &v.title, &v.artist.name)
+    }
+}
+
+impl From<Playlist> for SearchResult {
+    fn from(p: Playlist) -> Self {
+        let creator = p.creator.name.as_deref().unwrap_or_default();
+        SearchResult::new("playlist", p.uuid.clone(), p.get_name(), creator)
+    }
+}
+
 trait Named {
     fn get_name(&self) -> &str;
 }
@@ -166,6 +265,8 @@ impl Named for Album {
 pub struct Track {
     pub id: usize,
     pub title: String,
+    #[tabled(skip)]
+    pub version: Option<String>,
     pub duration: usize,
     #[tabled(skip)]
     pub track_number: usize,
@@ -205,24 +306,75 @@ impl Named for Track {
         &self.title
     }
 }
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all(deserialize = "camelCase"))]
+pub struct Video {
+    pub id: usize,
+    pub title: String,
+    pub version: Option<String>,
+    pub duration: usize,
+    pub track_number: Option<usize>,
+    pub volume_number: Option<usize>,
+    pub release_date: Option<String>,
+    pub image_id: Option<String>,
+    pub explicit: bool,
+    pub quality: Option<String>,
+    pub artist: Artist,
+    pub artists: Vec<Artist>,
+    // only set for videos that are part of an album
+    pub album: Option<Album>,
+}
+
+impl Video {
+    pub fn get_info(&self) -> String {
+        format!("[Video] {} - {}", self.artist.name, self.title)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all(deserialize = "UPPERCASE"))]
 pub struct TrackMix {
-    master_track_mix: Option<String>,
-    track_mix: Option<String>,
+    pub master_track_mix: Option<String>,
+    pub track_mix: Option<String>,
+}
+
+impl TrackMix {
+    /// The radio mix of the track, preferring the one built from the master quality catalog
+    pub fn radio(&self) -> Option<&str> {
+        self.master_track_mix
+            .as_deref()
+            .or(self.track_mix.as_deref())
+    }
+}
+
+/// A TIDAL generated mix, such as a track or artist radio
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mix {
+    pub id: String,
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MixPage {
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArtistMix {
+    pub id: String,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all(deserialize = "camelCase"))]
 pub struct Playlist {
-    uuid: String,
-    title: String,
-    number_of_tracks: usize,
-    number_of_videos: usize,
-    creator: PlaylistCreator,
-    description: String,
-    duration: usize,
-    promoted_artists: Vec<Artist>,
+    pub uuid: String,
+    pub title: String,
+    pub number_of_tracks: usize,
+    pub number_of_videos: usize,
+    pub creator: PlaylistCreator,
+    pub description: Option<String>,
+    pub duration: usize,
+    pub promoted_artists: Vec<Artist>,
 }
 impl Named for Playlist {
     fn get_name(&self) -> &str {
@@ -232,10 +384,11 @@ impl Named for Playlist {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PlaylistCreator {
-    id: usize,
-    name: String,
+    pub id: usize,
+    // playlists curated by TIDAL have a creator without a name
+    pub name: Option<String>,
     #[serde(alias = "type")]
-    creator_type: Option<String>,
+    pub creator_type: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -251,11 +404,17 @@ pub struct PlaybackManifest {
 impl PlaybackManifest {
     pub fn get_file_extension(&self) -> Option<&str> {
         match self.mime_type.as_str() {
+            "audio/mp4" if self.is_flac_in_mp4() => Some("flac"),
             "audio/mp4" => Some("m4a"),
             "audio/flac" => Some("flac"),
             _ => None,
         }
     }
+
+    /// FLAC streams packaged in an MP4 container, which are remuxed to native FLAC
+    pub fn is_flac_in_mp4(&self) -> bool {
+        self.mime_type == "audio/mp4" && self.codecs.eq_ignore_ascii_case("flac")
+    }
 }
 
 impl fmt::Display for PlaybackManifest {
@@ -329,6 +488,101 @@ impl clap::ValueEnum for AudioQuality {
     }
 }
 
+#[derive(SerializeDisplay, DeserializeFromStr, Clone, Debug, Copy)]
+///Maximum height of downloaded music videos
+pub enum VideoResolution {
+    R360,
+    R480,
+    R720,
+    R1080,
+}
+
+impl VideoResolution {
+    pub fn height(&self) -> u32 {
+        match self {
+            VideoResolution::R360 => 360,
+            VideoResolution::R480 => 480,
+            VideoResolution::R720 => 720,
+            VideoResolution::R1080 => 1080,
+        }
+    }
+}
+
+impl fmt::Display for VideoResolution {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.height())
+    }
+}
+
+impl FromStr for VideoResolution {
+    type Err = String;
+    fn from_str(input: &str) -> Result<VideoResolution, Self::Err> {
+        match input {
+            "360" => Ok(VideoResolution::R360),
+            "480" => Ok(VideoResolution::R480),
+            "720" => Ok(VideoResolution::R720),
+            "1080" => Ok(VideoResolution::R1080),
+            _ => Err("Error".to_string()),
+        }
+    }
+}
+
+impl clap::ValueEnum for VideoResolution {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::R360, Self::R480, Self::R720, Self::R1080]
+    }
+
+    fn to_possible_value<'a>(&self) -> Option<clap::PossibleValue<'a>> {
+        match self {
+            Self::R360 => Some(clap::PossibleValue::new("360")),
+            Self::R480 => Some(clap::PossibleValue::new("480")),
+            Self::R720 => Some(clap::PossibleValue::new("720")),
+            Self::R1080 => Some(clap::PossibleValue::new("1080")),
+        }
+    }
+}
+
+#[derive(SerializeDisplay, DeserializeFromStr, Clone, Debug, Copy, PartialEq, Eq, Hash)]
+///Square resolution of album cover images, or the originally uploaded image
+pub enum CoverResolution {
+    R80,
+    R160,
+    R320,
+    R640,
+    R1280,
+    Origin,
+}
+
+impl fmt::Display for CoverResolution {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let str = match self {
+            CoverResolution::R80 => "80",
+            CoverResolution::R160 => "160",
+            CoverResolution::R320 => "320",
+            CoverResolution::R640 => "640",
+            CoverResolution::R1280 => "1280",
+            CoverResolution::Origin => "origin",
+        };
+        fmt.write_str(str)?;
+        Ok(())
+    }
+}
+
+impl FromStr for CoverResolution {
+    type Err = String;
+    fn from_str(input: &str) -> Result<CoverResolution, Self::Err> {
+        match input {
+            "80" => Ok(CoverResolution::R80),
+            "160" => Ok(CoverResolution::R160),
+            "320" => Ok(CoverResolution::R320),
+            "640" => Ok(CoverResolution::R640),
+            "1280" => Ok(CoverResolution::R1280),
+            "origin" => Ok(CoverResolution::Origin),
+            _ => Err("Error".to_string()),
+        }
+    }
+}
+
 #[derive(SerializeDisplay, DeserializeFromStr, Clone, Debug)]
 pub enum AudioMode {
     Stereo,
@@ -418,12 +672,14 @@ impl FromStr for AssetPresentation {
 #[derive(SerializeDisplay, DeserializeFromStr, Debug)]
 pub enum EncryptionType {
     None,
+    OldAes,
 }
 
 impl fmt::Display for EncryptionType {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         let str = match self {
             EncryptionType::None => "NONE",
+            EncryptionType::OldAes => "OLD_AES",
         };
         fmt.write_str(str)?;
         Ok(())
@@ -435,6 +691,7 @@ impl FromStr for EncryptionType {
     fn from_str(input: &str) -> Result<EncryptionType, Self::Err> {
         match input {
             "NONE" => Ok(EncryptionType::None),
+            "OLD_AES" => Ok(EncryptionType::OldAes),
             _ => Err("Error".to_string()),
         }
     }
@@ -2,7 +2,6 @@ use super::ApiClient;
 use anyhow::Error;
 use serde::de::DeserializeOwned;
 use std::{ops::Deref, sync::Arc};
-use tabled::{Table, TableIteratorExt, Tabled};
 
 pub struct SearchClient(Arc<ApiClient>);
 
@@ -26,17 +25,12 @@ impl SearchClient {
         url: &str,
         query: &str,
         max: Option<usize>,
-    ) -> Result<Table, Error>
+    ) -> Result<Vec<T>, Error>
     where
-        T: DeserializeOwned + 'a + Tabled,
+        T: DeserializeOwned + 'a,
     {
         let url = format!("{}/search/{}", self.api_base, url);
         let query = ("query".to_string(), query.to_string());
-        let table = self
-            .get_items::<T>(&url, Some(vec![query]), max)
-            .await?
-            .table();
-
-        Ok(table)
+        self.get_items::<T>(&url, Some(vec![query]), max).await
     }
 }
@@ -0,0 +1,80 @@
+use anyhow::{anyhow, Error};
+use std::ops::Range;
+
+/// An MP4 atom (box) located in a buffer, `range` includes the atom header.
+pub struct Atom {
+    pub kind: [u8; 4],
+    pub header: usize,
+    pub range: Range<usize>,
+}
+
+impl Atom {
+    pub fn body(&self) -> Range<usize> {
+        self.range.start + self.header..self.range.end
+    }
+}
+
+pub fn parse_atoms(data: &[u8], range: Range<usize>) -> Result<Vec<Atom>, Error> {
+    let mut atoms = Vec::new();
+    let mut pos = range.start;
+    while pos + 8 <= range.end {
+        let size = read_u32(data, pos)? as usize;
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into()?;
+        let (header, len) = match size {
+            // the atom extends to the end of its parent
+            0 => (8, range.end - pos),
+            // 64 bit extended size
+            1 => (16, read_u64(data, pos + 8)? as usize),
+            _ => (8, size),
+        };
+        if len < header || pos + len > range.end {
+            return Err(anyhow!(
+                "Invalid size of MP4 atom {}",
+                String::from_utf8_lossy(&kind)
+            ));
+        }
+        atoms.push(Atom {
+            kind,
+            header,
+            range: pos..pos + len,
+        });
+        pos += len;
+    }
+    Ok(atoms)
+}
+
+pub fn read_u32(data: &[u8], pos: usize) -> Result<u32, Error> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .ok_or_else(|| anyhow!("Unexpected end of MP4 file"))?;
+    Ok(u32::from_be_bytes(bytes.try_into()?))
+}
+
+pub fn read_u64(data: &[u8], pos: usize) -> Result<u64, Error> {
+    let bytes = data
+        .get(pos..pos + 8)
+        .ok_or_else(|| anyhow!("Unexpected end of MP4 file"))?;
+    Ok(u64::from_be_bytes(bytes.try_into()?))
+}
+
+/// Descends through nested atoms, returning the first atom matching the last kind in `path`.
+pub fn find_atom(
+    data: &[u8],
+    range: Range<usize>,
+    path: &[&[u8; 4]],
+) -> Result<Option<Atom>, Error> {
+    let (kind, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Ok(None),
+    };
+    for atom in parse_atoms(data, range)? {
+        if &atom.kind != *kind {
+            continue;
+        }
+        return match rest.is_empty() {
+            true => Ok(Some(atom)),
+            false => find_atom(data, atom.body(), rest),
+        };
+    }
+    Ok(None)
+}
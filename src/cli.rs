@@ -1,12 +1,18 @@
-use crate::{api::models::AudioQuality, config::CONFIG};
+use crate::api::models::{AudioQuality, VideoResolution};
+use crate::{config::CONFIG, models::Action};
+use anyhow::Error;
 use clap::{
     arg,
     builder::{
-        BoolishValueParser, EnumValueParser, NonEmptyStringValueParser,
-        RangedU64ValueParser,
+        BoolishValueParser, EnumValueParser, NonEmptyStringValueParser, PathBufValueParser,
+        PossibleValuesParser, RangedU64ValueParser,
     },
     Arg, ArgMatches, Command,
 };
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::{fs::File, str::FromStr};
 
 pub fn cli() -> Command<'static> {
     Command::new(env!("CARGO_PKG_NAME"))
@@ -16,6 +22,8 @@ pub fn cli() -> Command<'static> {
         .subcommand_required(true)
         .disable_help_subcommand(true)
         .subcommand(get())
+        .subcommand(search())
+        .subcommand(sync())
         .subcommand(
             Command::new("login").about("Login or re-authenticates with the current access token"),
         )
@@ -31,10 +39,37 @@ fn get() -> Command<'static> {
             arg!(<URL>)
                 .multiple_values(true)
                 .min_values(1)
-                .required(true)
+                .required_unless_present_any(["input-file", "job"])
                 .value_parser(NonEmptyStringValueParser::new())
-                .help("One or multiple space separated URLs to download"),
+                .help("One or multiple space separated URLs to download, or a search query to pick downloads from. Use - to read URLs from stdin"),
+        )
+        .arg(
+            Arg::new("input-file")
+                .long("input-file")
+                .required(false)
+                .takes_value(true)
+                .value_parser(PathBufValueParser::new())
+                .value_name("path")
+                .help("File to read URLs from, one per line. Blank lines and lines starting with # are ignored"),
+        )
+        .arg(
+            Arg::new("job")
+                .long("job")
+                .required(false)
+                .takes_value(true)
+                .value_parser(PathBufValueParser::new())
+                .value_name("path")
+                .help("TOML or JSON job file of URLs, each with its own overrides of the config"),
         )
+        .arg(
+            Arg::new("radio")
+                .long("radio")
+                .required(false)
+                .takes_value(false)
+                .help("Download the radio mix of the given tracks and artists instead"),
+        )
+        .arg(search_type().help("Type of content to search for when given a query"))
+        .arg(search_limit())
         .arg(
             Arg::new("downloads")
                 .short('d')
@@ -67,6 +102,17 @@ fn get() -> Command<'static> {
                 .value_parser(EnumValueParser::<AudioQuality>::new())
                 .help("Requested audio quality of tracks"),
         )
+        .arg(
+            Arg::new("video-resolution")
+                .short('r')
+                .long("video-resolution")
+                .display_order(1)
+                .required(false)
+                .takes_value(true)
+                .value_parser(EnumValueParser::<VideoResolution>::new())
+                .value_name("height")
+                .help("Maximum resolution of music videos"),
+        )
         .arg(
             Arg::new("progress")
                 .short('p')
@@ -91,9 +137,97 @@ fn get() -> Command<'static> {
         )
 }
 
+fn search() -> Command<'static> {
+    Command::new("search")
+        .about("Searches TIDAL and lists the IDs and URLs of the results")
+        .arg(
+            arg!(<QUERY>)
+                .required(true)
+                .value_parser(NonEmptyStringValueParser::new())
+                .help("Text to search for"),
+        )
+        .arg(search_type().help("Type of content to search for"))
+        .arg(search_limit())
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .required(false)
+                .takes_value(false)
+                .help("Print the results as JSON instead of a table"),
+        )
+        .arg(
+            Arg::new("interactive")
+                .short('i')
+                .long("interactive")
+                .required(false)
+                .takes_value(false)
+                .conflicts_with("json")
+                .help("Pick results to download from a list"),
+        )
+}
+
+fn sync() -> Command<'static> {
+    Command::new("sync")
+        .about("Downloads the tracks added to a playlist, album, mix or favorites:tracks since it was last synced")
+        .arg(
+            arg!(<URL>)
+                .required(true)
+                .value_parser(NonEmptyStringValueParser::new())
+                .help("Link of the source to sync"),
+        )
+        .arg(
+            Arg::new("delete")
+                .long("delete")
+                .required(false)
+                .takes_value(false)
+                .help("Delete the files of tracks that are no longer in the source"),
+        )
+        .arg(
+            Arg::new("move-to")
+                .long("move-to")
+                .required(false)
+                .takes_value(true)
+                .conflicts_with("delete")
+                .value_parser(PathBufValueParser::new())
+                .value_name("path")
+                .help("Move the files of tracks that are no longer in the source into this folder"),
+        )
+}
+
+fn search_type() -> Arg<'static> {
+    Arg::new("type")
+        .short('t')
+        .long("type")
+        .required(false)
+        .takes_value(true)
+        .default_value("track")
+        .value_parser(PossibleValuesParser::new([
+            "track", "album", "artist", "playlist", "video",
+        ]))
+}
+
+fn search_limit() -> Arg<'static> {
+    Arg::new("limit")
+        .short('l')
+        .long("limit")
+        .required(false)
+        .takes_value(true)
+        .default_value("10")
+        .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+        .value_name("number")
+        .help("Maximum number of search results to list")
+}
+
 pub async fn parse_config_flags(matches: &ArgMatches) {
     let mut config = CONFIG.write().await;
-    let flags = ["downloads", "workers", "progress", "singles", "quality"];
+    let flags = [
+        "downloads",
+        "workers",
+        "progress",
+        "singles",
+        "quality",
+        "video-resolution",
+    ];
     for flag in flags {
         match flag {
             "downloads" => set_val::<u8>(&mut config.downloads, flag, matches),
@@ -101,12 +235,15 @@ pub async fn parse_config_flags(matches: &ArgMatches) {
             "progress" => set_val::<bool>(&mut config.show_progress, flag, matches),
             "singles" => set_val::<bool>(&mut config.include_singles, flag, matches),
             "quality" => set_val::<AudioQuality>(&mut config.audio_quality, flag, matches),
+            "video-resolution" => {
+                set_val::<VideoResolution>(&mut config.video_resolution, flag, matches)
+            }
             _ => continue,
         };
     }
 }
 
-fn set_val<'a, T>(dst: &mut T, flag: &str, matches: &'a ArgMatches)
+fn set_val<T>(dst: &mut T, flag: &str, matches: &ArgMatches)
 where
     T: Send + Sync + Copy + Clone + 'static,
 {
@@ -114,3 +251,106 @@ where
         let _ = std::mem::replace(dst, *v);
     }
 }
+
+/// Collects the URLs passed to `get`, reading them from the input file, or stdin for `-`.
+///
+/// Duplicates are removed, comparing the content they link to where the URL is valid.
+pub fn get_targets(matches: &ArgMatches) -> Result<Vec<String>, Error> {
+    collect_targets(matches, &mut std::io::stdin().lock())
+}
+
+// the URLs of the input file and the arguments, with `-` read from `stdin`
+fn collect_targets(matches: &ArgMatches, stdin: &mut dyn BufRead) -> Result<Vec<String>, Error> {
+    let mut targets = Vec::new();
+    if let Some(path) = matches.get_one::<PathBuf>("input-file") {
+        targets.extend(read_targets(BufReader::new(File::open(path)?))?);
+    }
+    for url in matches.get_many::<String>("URL").into_iter().flatten() {
+        match url.as_str() {
+            "-" => targets.extend(read_targets(&mut *stdin)?),
+            _ => targets.push(url.to_owned()),
+        }
+    }
+
+    let mut seen = HashSet::new();
+    targets.retain(|url| match Action::from_str(url) {
+        Ok(action) => seen.insert(format!("{}/{}", action.kind, action.id)),
+        Err(_) => seen.insert(url.to_owned()),
+    });
+    Ok(targets)
+}
+
+fn read_targets(reader: impl BufRead) -> Result<Vec<String>, Error> {
+    let mut targets = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        // comments start a line, or follow a URL after whitespace
+        let url = match line.find(" #").or_else(|| line.find("\t#")) {
+            Some(i) => &line[..i],
+            None => &line,
+        };
+        let url = url.trim();
+        if !url.is_empty() && !url.starts_with('#') {
+            targets.push(url.to_string());
+        }
+    }
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const LIST: &str = "# albums to get
+https://tidal.com/browse/album/1
+
+   tidal://track/2   # with a comment
+\t
+#tidal://track/3
+https://tidal.com/browse/track/4\t# tab before the comment
+";
+
+    #[test]
+    fn reads_targets_skipping_blank_lines_and_comments() {
+        let targets = read_targets(Cursor::new(LIST)).unwrap();
+        assert_eq!(
+            targets,
+            [
+                "https://tidal.com/browse/album/1",
+                "tidal://track/2",
+                "https://tidal.com/browse/track/4"
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_stdin_for_a_dash() {
+        let path = std::env::temp_dir().join(format!("tdl-targets-{}.txt", std::process::id()));
+        std::fs::write(&path, "tidal://album/1\ntidal://album/5\n").unwrap();
+        let matches = cli().get_matches_from([
+            "tdl",
+            "get",
+            "--input-file",
+            path.to_str().unwrap(),
+            "tidal://track/9",
+            "-",
+        ]);
+        let (_, get) = matches.subcommand().unwrap();
+        let mut stdin = Cursor::new(LIST);
+        let targets = collect_targets(get, &mut stdin).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // the file first, then the arguments in order, and a link to the same album only once
+        assert_eq!(
+            targets,
+            [
+                "tidal://album/1",
+                "tidal://album/5",
+                "tidal://track/9",
+                "tidal://track/2",
+                "https://tidal.com/browse/track/4"
+            ]
+        );
+    }
+}
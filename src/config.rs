@@ -1,18 +1,31 @@
 use crate::api::models::Album;
 use crate::api::models::Artist;
 use crate::api::models::AudioQuality;
+use crate::api::models::CoverResolution;
+use crate::api::models::Mix;
+use crate::api::models::Playlist;
 use crate::api::models::Track;
+use crate::api::models::Video;
+use crate::api::models::VideoResolution;
+use crate::normalize::PathProfile;
+use crate::playlist::PlaylistFormat;
+use crate::template::Template;
 use anyhow::Error;
 use config::{Config, File, FileFormat};
+use deunicode::deunicode_char;
 use phf::phf_map;
+use reqwest_retry::policies::ExponentialBackoff;
 use sanitize_filename::sanitize;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use serde_with::NoneAsEmptyString;
+use std::borrow::Cow;
 use std::env::{var, VarError};
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -21,16 +34,58 @@ pub struct Settings {
     pub show_progress: bool,
     pub progress_refresh_rate: u8,
     pub include_singles: bool,
+    pub include_videos: bool,
+    pub video_resolution: VideoResolution,
     pub downloads: u8,
     pub workers: u8,
     pub download_cover: bool,
+    pub cover_name: String,
+    pub cover_resolution: CoverResolution,
+    pub verify_downloads: bool,
     pub cache_dir: String,
-    pub download_path: String,
+    pub quarantine_dir: String,
+    pub sync_dir: String,
+    pub download_path: Template,
+    pub video_path: Template,
+    pub mix_path: Template,
+    pub playlist_path: Template,
+    pub path_profile: PathProfile,
+    pub artist_separator: String,
+    pub playlist_formats: Vec<PlaylistFormat>,
+    pub album_playlists: bool,
+    #[serde(default)]
+    pub download_paths: DownloadPaths,
+    pub stream_retry: StreamRetry,
     pub login_key: LoginKey,
     pub api_key: ApiKey,
 }
 
 impl Settings {
+    /// Checks that every path template only uses the tokens it can be filled in with.
+    pub fn check_templates(&self) -> Result<(), Error> {
+        let tracks = track_tokens();
+        let check = |key: &str, template: &Template, names: &[&str]| {
+            template
+                .check(names)
+                .map_err(|e| anyhow::anyhow!("Invalid {key}: {e}"))
+        };
+        check("download_path", &self.download_path, &tracks)?;
+        for (key, template) in self.download_paths.templates() {
+            check(&format!("download_paths.{key}"), template, &tracks)?;
+        }
+        let mix = [tracks.clone(), MixPosition::token_names()].concat();
+        check("mix_path", &self.mix_path, &mix)?;
+        let playlist = [tracks, PlaylistPosition::token_names()].concat();
+        check("playlist_path", &self.playlist_path, &playlist)?;
+        let videos = [
+            Artist::token_names(),
+            Album::token_names(),
+            Video::token_names(),
+        ]
+        .concat();
+        check("video_path", &self.video_path, &videos)
+    }
+
     pub fn save(&self) -> Result<(), Error> {
         let config_file = get_config_file()?;
         let config_dir = get_config_dir()?;
@@ -60,13 +115,104 @@ pub struct LoginKey {
     pub expires_after: Option<i64>,
 }
 
+/// Retry policy for reconnecting a dropped audio stream
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamRetry {
+    pub max_retries: u32,
+    pub min_interval_ms: u64,
+    pub max_interval_ms: u64,
+    pub backoff_exponent: u32,
+}
+
+impl StreamRetry {
+    pub fn policy(&self) -> ExponentialBackoff {
+        ExponentialBackoff {
+            max_n_retries: self.max_retries,
+            min_retry_interval: Duration::from_millis(self.min_interval_ms),
+            max_retry_interval: Duration::from_millis(self.max_interval_ms),
+            backoff_exponent: self.backoff_exponent,
+        }
+    }
+}
+
+/// Path templates of tracks by the type of their album, and the folder keys that make up a
+/// template of their own in place of `download_path`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DownloadPaths {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_path: Option<Template>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist: Option<Template>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<Template>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track: Option<Template>,
+    #[serde(rename = "ALBUM", default, skip_serializing_if = "Option::is_none")]
+    pub type_album: Option<Template>,
+    #[serde(rename = "EP", default, skip_serializing_if = "Option::is_none")]
+    pub type_ep: Option<Template>,
+    #[serde(rename = "SINGLE", default, skip_serializing_if = "Option::is_none")]
+    pub type_single: Option<Template>,
+    #[serde(
+        rename = "COMPILATION",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub type_compilation: Option<Template>,
+}
+
+impl DownloadPaths {
+    /// The template of a track on an album of `album_type`, falling back to the template made
+    /// of the `base_path`, `artist`, `album` and `track` keys, and then to `default`.
+    pub fn template<'a>(
+        &'a self,
+        album_type: Option<&str>,
+        default: &'a Template,
+    ) -> Cow<'a, Template> {
+        let by_type = match album_type {
+            Some("ALBUM") => &self.type_album,
+            Some("EP") => &self.type_ep,
+            Some("SINGLE") => &self.type_single,
+            Some("COMPILATION") => &self.type_compilation,
+            _ => &None,
+        };
+        if let Some(template) = by_type {
+            return Cow::Borrowed(template);
+        }
+        // blank keys are left out instead of creating a folder
+        let folders: Vec<&Template> = [&self.base_path, &self.artist, &self.album, &self.track]
+            .into_iter()
+            .flatten()
+            .filter(|key| !key.is_empty())
+            .collect();
+        match folders.is_empty() {
+            true => Cow::Borrowed(default),
+            false => Cow::Owned(Template::join(folders, "/")),
+        }
+    }
+
+    fn templates(&self) -> impl Iterator<Item = (&str, &Template)> {
+        [
+            ("base_path", &self.base_path),
+            ("artist", &self.artist),
+            ("album", &self.album),
+            ("track", &self.track),
+            ("ALBUM", &self.type_album),
+            ("EP", &self.type_ep),
+            ("SINGLE", &self.type_single),
+            ("COMPILATION", &self.type_compilation),
+        ]
+        .into_iter()
+        .filter_map(|(key, template)| template.as_ref().map(|t| (key, t)))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ApiKey {
     pub client_id: String,
     pub client_secret: String,
 }
 
-
 trait UnwrapEmptyString<T: ToString> {
     fn unwrap_empty_string(self) -> String;
 }
@@ -83,26 +229,38 @@ where
     }
 }
 
+/// The tokens of a track's path template, which are those of the track, its album and artist
+pub fn track_tokens() -> Vec<&'static str> {
+    [
+        Artist::token_names(),
+        Album::token_names(),
+        Track::token_names(),
+    ]
+    .concat()
+}
+
 pub trait DownloadPath<T>
 where
     Self: Sized + Clone,
     T: TokenMap<Self> + 'static + Copy,
 {
-    fn replace_path(&self, path: &str) -> String {
-        let map = T::token_map();
-        let mut x = path.to_string();
-        map.entries().for_each(|entry| {
-            if x.contains(entry.0) {
-                x = x.replace(entry.0, &entry.1.get_token(self));
-            };
-        });
-        x
+    fn get_token(&self, name: &str, settings: &Settings) -> Option<String> {
+        T::token_map()
+            .get(name)
+            .map(|token| token.get_token(self, settings))
+    }
+
+    fn token_names() -> Vec<&'static str> {
+        T::token_map().keys().copied().collect()
     }
 }
 
 impl DownloadPath<ArtistTokens> for Artist {}
 impl DownloadPath<AlbumTokens> for Album {}
 impl DownloadPath<TrackTokens> for Track {}
+impl DownloadPath<VideoTokens> for Video {}
+impl DownloadPath<MixTokens> for MixPosition {}
+impl DownloadPath<PlaylistTokens> for PlaylistPosition {}
 
 pub trait TokenMap<T>
 where
@@ -111,12 +269,12 @@ where
 {
     fn token_map() -> &'static phf::Map<&'static str, Self>;
 
-    fn get_token(self, _: &T) -> String;
+    fn get_token(self, _: &T, settings: &Settings) -> String;
 }
 
 static ARTIST_TOKEN_MAP: phf::Map<&'static str, ArtistTokens> = phf_map! {
-    "{artist_name}" =>  ArtistTokens::Name,
-    "{artist_id}" => ArtistTokens::ID
+    "artist_name" =>  ArtistTokens::Name,
+    "artist_id" => ArtistTokens::ID
 };
 
 #[derive(Clone, Copy)]
@@ -130,7 +288,7 @@ impl TokenMap<Artist> for ArtistTokens {
         &ARTIST_TOKEN_MAP
     }
 
-    fn get_token(self, a: &Artist) -> String {
+    fn get_token(self, a: &Artist, _: &Settings) -> String {
         let val = match self {
             ArtistTokens::ID => a.id.to_string(),
             ArtistTokens::Name => a.name.to_string(),
@@ -140,21 +298,27 @@ impl TokenMap<Artist> for ArtistTokens {
 }
 
 static ALBUM_TOKEN_MAP: phf::Map<&'static str, AlbumTokens> = phf_map! {
-    "{album_id}" => AlbumTokens::ID,
-    "{album_name}" => AlbumTokens::Title,
-    "{album_duration}" => AlbumTokens::Duration,
-    "{album_tracks}" => AlbumTokens::NumberOfTracks,
-    "{album_explicit}" => AlbumTokens::Explicit,
-    "{album_quality}" => AlbumTokens::AudioQuality,
-    "{album_release}" => AlbumTokens::ReleaseDate,
-    "{album_release_year}" => AlbumTokens::ReleaseYear,
+    "album_id" => AlbumTokens::ID,
+    "album_name" => AlbumTokens::Title,
+    "album_duration" => AlbumTokens::Duration,
+    "album_tracks" => AlbumTokens::NumberOfTracks,
+    "album_explicit" => AlbumTokens::Explicit,
+    "album_quality" => AlbumTokens::AudioQuality,
+    "album_release" => AlbumTokens::ReleaseDate,
+    "album_release_year" => AlbumTokens::ReleaseYear,
+    "album_version" => AlbumTokens::Version,
+    "album_type" => AlbumTokens::Type,
+    "album_volumes" => AlbumTokens::NumberOfVolumes,
+    "album_artists" => AlbumTokens::Artists,
+    "album_artist_first_letter" => AlbumTokens::ArtistFirstLetter,
+    "audio_mode" => AlbumTokens::AudioMode,
 };
 impl TokenMap<Album> for AlbumTokens {
     fn token_map() -> &'static phf::Map<&'static str, Self> {
         &ALBUM_TOKEN_MAP
     }
 
-    fn get_token(self, a: &Album) -> String {
+    fn get_token(self, a: &Album, settings: &Settings) -> String {
         let a = match self {
             AlbumTokens::ID => a.id.to_string(),
             AlbumTokens::Title => a.title.as_ref().unwrap_empty_string(),
@@ -173,6 +337,26 @@ impl TokenMap<Album> for AlbumTokens {
                 .split('-')
                 .next()
                 .unwrap_empty_string(),
+            AlbumTokens::Version => a.version.as_ref().unwrap_empty_string(),
+            AlbumTokens::Type => a.album_type.as_ref().unwrap_empty_string(),
+            AlbumTokens::NumberOfVolumes => a.number_of_volumes.unwrap_empty_string(),
+            AlbumTokens::Artists => match &a.artists {
+                Some(artists) if !artists.is_empty() => {
+                    join_names(artists, &settings.artist_separator)
+                }
+                _ => a.artist.as_ref().map(|a| &a.name).unwrap_empty_string(),
+            },
+            AlbumTokens::ArtistFirstLetter => {
+                let artist = a.artist.as_ref().or(a.artists.iter().flatten().next());
+                first_letter(artist.map(|a| a.name.as_str()).unwrap_or_default())
+            }
+            AlbumTokens::AudioMode => a
+                .audio_modes
+                .iter()
+                .flatten()
+                .map(|m| m.to_string())
+                .collect::<Vec<String>>()
+                .join("+"),
         };
         sanitize(a)
     }
@@ -187,17 +371,46 @@ pub enum AlbumTokens {
     AudioQuality,
     ReleaseDate,
     ReleaseYear,
+    Version,
+    Type,
+    NumberOfVolumes,
+    Artists,
+    ArtistFirstLetter,
+    AudioMode,
+}
+
+// the names of artists, joined with the separator of the `artist_separator` setting
+fn join_names(artists: &[Artist], separator: &str) -> String {
+    artists
+        .iter()
+        .map(|a| a.name.as_str())
+        .collect::<Vec<&str>>()
+        .join(separator)
+}
+
+// the first letter of a name as an ASCII capital, skipping leading punctuation and spaces, or #
+// for names that start with a digit or have no letters
+fn first_letter(name: &str) -> String {
+    let first = name.chars().find(|c| c.is_alphanumeric());
+    match first.map(|c| deunicode_char(c).unwrap_or_default().to_uppercase()) {
+        Some(letter) if letter.starts_with(|c: char| c.is_ascii_uppercase()) => {
+            letter[..1].to_string()
+        }
+        _ => String::from("#"),
+    }
 }
 
 static TRACK_TOKEN_MAP: phf::Map<&'static str, TrackTokens> = phf_map! {
-   "{track_id}" => TrackTokens::ID,
-   "{track_name}" => TrackTokens::Title,
-   "{track_duration}" => TrackTokens::Duration,
-   "{track_num}" => TrackTokens::TrackNumber,
-   "{track_volume}" => TrackTokens::VolumeNumber,
-   "{track_isrc}" => TrackTokens::ISRC,
-   "{track_explicit}" => TrackTokens::Explicit,
-   "{track_quality}" => TrackTokens::AudioQuality,
+   "track_id" => TrackTokens::ID,
+   "track_name" => TrackTokens::Title,
+   "track_duration" => TrackTokens::Duration,
+   "track_num" => TrackTokens::TrackNumber,
+   "track_volume" => TrackTokens::VolumeNumber,
+   "track_isrc" => TrackTokens::ISRC,
+   "track_explicit" => TrackTokens::Explicit,
+   "track_quality" => TrackTokens::AudioQuality,
+   "track_artists" => TrackTokens::Artists,
+   "track_copyright" => TrackTokens::Copyright,
 };
 
 #[derive(Clone, Copy)]
@@ -210,13 +423,15 @@ pub enum TrackTokens {
     ISRC,
     Explicit,
     AudioQuality,
+    Artists,
+    Copyright,
 }
 impl TokenMap<Track> for TrackTokens {
     fn token_map() -> &'static phf::Map<&'static str, Self> {
         &TRACK_TOKEN_MAP
     }
 
-    fn get_token(self, v: &Track) -> String {
+    fn get_token(self, v: &Track, settings: &Settings) -> String {
         let a = match self {
             TrackTokens::ID => v.id.to_string(),
             TrackTokens::Title => v.title.clone(),
@@ -229,6 +444,154 @@ impl TokenMap<Track> for TrackTokens {
                 false => String::new(),
             },
             TrackTokens::AudioQuality => v.audio_quality.to_string(),
+            TrackTokens::Artists => match v.artists.is_empty() {
+                true => v.artist.name.clone(),
+                false => join_names(&v.artists, &settings.artist_separator),
+            },
+            TrackTokens::Copyright => v.copyright.clone(),
+        };
+        sanitize(a)
+    }
+}
+
+static VIDEO_TOKEN_MAP: phf::Map<&'static str, VideoTokens> = phf_map! {
+   "video_id" => VideoTokens::ID,
+   "video_name" => VideoTokens::Title,
+   "video_duration" => VideoTokens::Duration,
+   "video_num" => VideoTokens::TrackNumber,
+   "video_volume" => VideoTokens::VolumeNumber,
+   "video_explicit" => VideoTokens::Explicit,
+   "video_quality" => VideoTokens::Quality,
+   "video_release" => VideoTokens::ReleaseDate,
+   "video_release_year" => VideoTokens::ReleaseYear,
+};
+
+#[derive(Clone, Copy)]
+pub enum VideoTokens {
+    ID,
+    Title,
+    Duration,
+    TrackNumber,
+    VolumeNumber,
+    Explicit,
+    Quality,
+    ReleaseDate,
+    ReleaseYear,
+}
+impl TokenMap<Video> for VideoTokens {
+    fn token_map() -> &'static phf::Map<&'static str, Self> {
+        &VIDEO_TOKEN_MAP
+    }
+
+    fn get_token(self, v: &Video, _: &Settings) -> String {
+        let a = match self {
+            VideoTokens::ID => v.id.to_string(),
+            VideoTokens::Title => v.title.clone(),
+            VideoTokens::Duration => v.duration.to_string(),
+            VideoTokens::TrackNumber => v.track_number.unwrap_empty_string(),
+            VideoTokens::VolumeNumber => v.volume_number.unwrap_empty_string(),
+            VideoTokens::Explicit => match v.explicit {
+                true => String::from("E"),
+                false => String::new(),
+            },
+            VideoTokens::Quality => v.quality.as_ref().unwrap_empty_string(),
+            VideoTokens::ReleaseDate => v.release_date.as_ref().unwrap_empty_string(),
+            VideoTokens::ReleaseYear => v
+                .release_date
+                .as_ref()
+                .unwrap_empty_string()
+                .split('-')
+                .next()
+                .unwrap_empty_string(),
+        };
+        sanitize(a)
+    }
+}
+
+/// The place of a track in a mix, which fills in the tokens of `mix_path`
+#[derive(Clone)]
+pub struct MixPosition {
+    pub mix: Arc<Mix>,
+    pub index: usize,
+    /// the number of tracks in the mix
+    pub len: usize,
+}
+
+static MIX_TOKEN_MAP: phf::Map<&'static str, MixTokens> = phf_map! {
+   "mix_id" => MixTokens::ID,
+   "mix_name" => MixTokens::Title,
+   "mix_index" => MixTokens::Index,
+};
+
+#[derive(Clone, Copy)]
+pub enum MixTokens {
+    ID,
+    Title,
+    Index,
+}
+impl TokenMap<MixPosition> for MixTokens {
+    fn token_map() -> &'static phf::Map<&'static str, Self> {
+        &MIX_TOKEN_MAP
+    }
+
+    fn get_token(self, m: &MixPosition, _: &Settings) -> String {
+        let a = match self {
+            MixTokens::ID => m.mix.id.clone(),
+            MixTokens::Title => m.mix.title.clone(),
+            // zero padded so the files sort in mix order
+            // zero padded to the digits of the track count, at least two
+            MixTokens::Index => {
+                let width = m.len.max(m.index + 1).to_string().len().max(2);
+                format!("{:0width$}", m.index + 1)
+            }
+        };
+        sanitize(a)
+    }
+}
+
+/// The place of a track in a playlist, which fills in the tokens of `playlist_path`
+#[derive(Clone)]
+pub struct PlaylistPosition {
+    pub playlist: Arc<Playlist>,
+    pub index: usize,
+}
+
+static PLAYLIST_TOKEN_MAP: phf::Map<&'static str, PlaylistTokens> = phf_map! {
+   "playlist_name" => PlaylistTokens::Title,
+   "playlist_uuid" => PlaylistTokens::Uuid,
+   "playlist_creator" => PlaylistTokens::Creator,
+   "playlist_index" => PlaylistTokens::Index,
+};
+
+#[derive(Clone, Copy)]
+pub enum PlaylistTokens {
+    Title,
+    Uuid,
+    Creator,
+    Index,
+}
+impl TokenMap<PlaylistPosition> for PlaylistTokens {
+    fn token_map() -> &'static phf::Map<&'static str, Self> {
+        &PLAYLIST_TOKEN_MAP
+    }
+
+    fn get_token(self, p: &PlaylistPosition, _: &Settings) -> String {
+        let a = match self {
+            PlaylistTokens::Title => p.playlist.title.clone(),
+            PlaylistTokens::Uuid => p.playlist.uuid.clone(),
+            // playlists curated by TIDAL have a creator without a name
+            PlaylistTokens::Creator => p
+                .playlist
+                .creator
+                .name
+                .clone()
+                .unwrap_or_else(|| "TIDAL".to_string()),
+            // zero padded to the digits of the track count, at least two
+            PlaylistTokens::Index => {
+                let len = p.playlist.number_of_tracks.max(p.index + 1);
+                let width = len.to_string().len().max(2);
+                format!("{:0width$}", p.index + 1)
+            }
         };
         sanitize(a)
     }
@@ -239,13 +602,27 @@ pub fn get_config() -> Result<Settings, Error> {
         .set_default("audio_quality", "HI_RES")?
         .set_default("show_progress", true)?
         .set_default("include_singles", true)?
+        .set_default("include_videos", true)?
+        .set_default("video_resolution", "1080")?
         .set_default("progress_refresh_rate", 5)?
         .set_default("login_key.device_code", "")?
         .set_default("login_key.country_code", "")?
         .set_default("download_cover", true)?
+        .set_default("cover_name", "cover.jpg")?
+        .set_default("cover_resolution", "1280")?
         .set_default("downloads", 3)?
         .set_default("workers", 1)?
+        .set_default("stream_retry.max_retries", 5)?
+        .set_default("stream_retry.min_interval_ms", 500)?
+        .set_default("stream_retry.max_interval_ms", 30000)?
+        .set_default("stream_retry.backoff_exponent", 2)?
+        .set_default("verify_downloads", true)?
         .set_default("cache_dir", get_cache_dir().expect("Failed to get cache dir"))?
+        .set_default(
+            "quarantine_dir",
+            get_quarantine_dir().expect("Failed to get quarantine dir"),
+        )?
+        .set_default("sync_dir", get_sync_dir().expect("Failed to get sync dir"))?
         .set_default("login_key.access_token", "")?
         .set_default("login_key.refresh_token", "")?
         .set_default("login_key.expires_after", 0)?
@@ -255,9 +632,17 @@ pub fn get_config() -> Result<Settings, Error> {
             "VJKhDFqJPqvsPVNBV6ukXTJmwlvbttP7wlMlrc72se4=",
         )?
         .set_default("download_path", "$HOME/Music/{artist_name}/{album_name} [{album_id}] [{album_release_year}]/{track_num} - {track_name}")?
+        .set_default("mix_path", "$HOME/Music/Mixes/{mix_name} [{mix_id}]/{mix_index} - {artist_name} - {track_name}")?
+        .set_default("playlist_path", "")?
+        .set_default("path_profile", "posix")?
+        .set_default("artist_separator", ", ")?
+        .set_default("playlist_formats", vec!["m3u8"])?
+        .set_default("album_playlists", false)?
+        .set_default("video_path", "$HOME/Music/{artist_name}/Videos/{video_name} [{video_id}]")?
         .add_source(File::new(CONFIG_FILE.as_str(), FileFormat::Toml).required(false))
         .build()?;
     let settings: Settings = config.try_deserialize()?;
+    settings.check_templates()?;
     settings.save()?;
 
     Ok(settings)
@@ -269,7 +654,7 @@ fn get_config_dir() -> Result<String, Error> {
         Err(VarError::NotPresent) => {
             let home_dir = var("HOME")?;
             Path::new(&home_dir).join(".config")
-        },
+        }
         Err(e) => return Err(e.into()),
     };
 
@@ -281,7 +666,6 @@ fn get_config_dir() -> Result<String, Error> {
     }
 }
 
-
 fn get_cache_dir() -> Result<String, Error> {
     let config_dir = get_config_dir()?;
     let cache_dir = PathBuf::from(config_dir).join("cache");
@@ -291,8 +675,26 @@ fn get_cache_dir() -> Result<String, Error> {
         .ok_or_else(|| anyhow::anyhow!("Failed to convert path to string"))
 }
 
+fn get_quarantine_dir() -> Result<String, Error> {
+    let config_dir = get_config_dir()?;
+    let quarantine_dir = PathBuf::from(config_dir).join("quarantine");
+    quarantine_dir
+        .to_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| anyhow::anyhow!("Failed to convert path to string"))
+}
+
+fn get_sync_dir() -> Result<String, Error> {
+    let config_dir = get_config_dir()?;
+    let sync_dir = PathBuf::from(config_dir).join("sync");
+    sync_dir
+        .to_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| anyhow::anyhow!("Failed to convert path to string"))
+}
+
 fn get_config_file() -> Result<String, Error> {
-    let config_dir = get_config_dir()?; 
+    let config_dir = get_config_dir()?;
     let config_file = PathBuf::from(config_dir).join("config.toml");
     config_file
         .to_str()
@@ -305,3 +707,19 @@ lazy_static::lazy_static! {
    pub static ref CONFIG_FILE: String = get_config_file().expect("Failed to get config file");
    pub static ref CONFIG: RwLock<Settings> = RwLock::new(get_config().expect("Unable to get configuration"));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_letters() {
+        assert_eq!(first_letter("Eminem"), "E");
+        assert_eq!(first_letter("ólafur Arnalds"), "O");
+        assert_eq!(first_letter("'Til Tuesday"), "T");
+        assert_eq!(first_letter("  ...And You Will Know Us"), "A");
+        assert_eq!(first_letter("2Pac"), "#");
+        assert_eq!(first_letter("!!!"), "#");
+        assert_eq!(first_letter(""), "#");
+    }
+}
@@ -1,20 +1,32 @@
 use crate::api::{models::*, TidalClient, CLIENT};
-use crate::config::{CONFIG, DownloadPath};
+use crate::config::{DownloadPath, MixPosition, PlaylistPosition, Settings, StreamRetry, CONFIG};
 
+use crate::jobs::Job;
 use crate::models::*;
+use crate::normalize::{truncate, MAX_NAME_BYTES};
+use crate::playlist::{PlaylistEntry, PlaylistFile, PlaylistSlot};
+use crate::remux::remux_flac;
+use crate::tags::{write_tags, TrackTags};
+use crate::template::Template;
+use crate::verify::verify_flac;
 use anyhow::{anyhow, Error};
 use futures::Future;
 use indicatif::{MultiProgress, ProgressDrawTarget};
 use log::{debug, info};
-use metaflac::block::PictureType::CoverFront;
-use metaflac::Tag;
+use reqwest::header::RANGE;
+use reqwest::StatusCode;
+use retry_policies::{RetryDecision, RetryPolicy};
+use sanitize_filename::sanitize;
+use std::borrow::Cow;
 use std::cmp::min;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::str::FromStr;
-use std::sync::Arc;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use std::sync::{Arc, Mutex};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::time::sleep;
 use tokio::try_join;
 
 use tokio::sync::mpsc::{self, Receiver, Sender};
@@ -24,10 +36,21 @@ use tokio_stream::StreamExt;
 pub type ChannelValue = Pin<Box<dyn Future<Output = Result<bool, Error>> + Send>>;
 pub type ReceiveChannel = Receiver<ChannelValue>;
 
+// a download that fails verification is quarantined and downloaded again up to this many times
+const VERIFY_ATTEMPTS: usize = 2;
+
 pub async fn dispatch_downloads(
-    urls: Vec<String>,
+    jobs: Vec<Job>,
     client: TidalClient,
-) -> Result<(Vec<JoinHandle<()>>, ReceiveChannel, ReceiveChannel), Error> {
+) -> Result<
+    (
+        Vec<JoinHandle<()>>,
+        ReceiveChannel,
+        ReceiveChannel,
+        Arc<RunSummary>,
+    ),
+    Error,
+> {
     let config = CONFIG.read().await;
     let progress = setup_multi_progress(config.show_progress, config.progress_refresh_rate);
     let client = Arc::new(client);
@@ -37,30 +60,57 @@ pub async fn dispatch_downloads(
     let buffer_size = config.workers as usize + config.downloads as usize;
     let (dl_tx, dl_rx) = mpsc::channel(buffer_size);
     let (worker_tx, worker_rx) = mpsc::channel(config.workers as usize);
+    let summary = Arc::new(RunSummary::default());
 
     let task = DownloadTask {
         dl_channel: dl_tx,
         worker_channel: worker_tx,
         client,
+        settings: Arc::new(config.clone()),
         progress,
+        summary: summary.clone(),
+        covers: Arc::new(Mutex::new(HashSet::new())),
+        synced: Arc::default(),
     };
     debug!("Download Task");
-    let mut handles = Vec::with_capacity(urls.len());
+    let mut handles = Vec::with_capacity(jobs.len());
     // for every url supplied to the get command
-    for url in urls {
+    for Job {
+        url,
+        settings,
+        radio,
+        synced,
+    } in jobs
+    {
         let action = match Action::from_str(&url) {
             Ok(a) => a,
-            Err(_) => continue, // skip the current url if it's not valid.
+            // skip the current url if it's not valid.
+            Err(e) => {
+                summary.add_failed();
+                eprintln!("Skipping '{url}': {e}");
+                continue;
+            }
         };
+        if radio && !matches!(action.kind, ActionKind::Track | ActionKind::Artist) {
+            summary.add_failed();
+            eprintln!("Skipping '{url}': only tracks and artists have a radio mix");
+            continue;
+        }
         let id = action.id;
-        let task = task.clone();
+        let task = DownloadTask {
+            settings,
+            synced,
+            ..task.clone()
+        };
 
         //spawn the download task for each URL in a new thread
         let handle = tokio::task::spawn(async move {
             let res = match action.kind {
+                ActionKind::Track if radio => task.download_track_radio(id).await,
+                ActionKind::Artist if radio => task.download_artist_radio(id).await,
                 ActionKind::Track => {
                     let channel = task.worker_channel.clone();
-                    let job = Box::pin(task.download_track(id));
+                    let job = Box::pin(task.download_track(id, None));
                     match channel.send(job).await {
                         Ok(_) => Ok(true),
                         Err(_) => Err(anyhow!("Error submitting track to worker queue")),
@@ -69,6 +119,16 @@ pub async fn dispatch_downloads(
                 ActionKind::Album => task.download_list(ActionKind::Album, id).await,
                 ActionKind::Artist => task.download_artist(id).await,
                 ActionKind::Playlist => task.download_list(ActionKind::Playlist, id).await,
+                ActionKind::Video => {
+                    let channel = task.worker_channel.clone();
+                    let job = Box::pin(task.download_video(id));
+                    match channel.send(job).await {
+                        Ok(_) => Ok(true),
+                        Err(_) => Err(anyhow!("Error submitting video to worker queue")),
+                    }
+                }
+                ActionKind::Mix => task.download_mix(id).await,
+                ActionKind::Favorites => task.download_favorites(&id).await,
             };
             match res {
                 Ok(_) => {}
@@ -79,7 +139,7 @@ pub async fn dispatch_downloads(
         handles.push(handle)
     }
 
-    Ok((handles, dl_rx, worker_rx))
+    Ok((handles, dl_rx, worker_rx, summary))
 }
 
 #[derive(Clone)]
@@ -88,12 +148,36 @@ pub struct DownloadTask {
     pub dl_channel: Sender<ChannelValue>,
     pub worker_channel: Sender<ChannelValue>,
     pub client: Arc<TidalClient>,
+    /// the settings of the job this task downloads for
+    pub settings: Arc<Settings>,
+    pub summary: Arc<RunSummary>,
+    /// album cover files already claimed by a track during this run
+    pub covers: Arc<Mutex<HashSet<PathBuf>>>,
+    /// the files of tracks of the job that are already on disk
+    pub synced: Arc<HashMap<usize, PathBuf>>,
+}
+
+/// A track downloaded as part of a list, listed in the list's playlist files
+pub struct ListSlot {
+    pub position: Option<ListPosition>,
+    pub playlist: Option<PlaylistSlot>,
+}
+
+/// Where a track is in a list, placing it with the path template of the list instead of
+/// the download path
+pub enum ListPosition {
+    Mix(MixPosition),
+    Playlist(PlaylistPosition),
 }
 
 impl DownloadTask {
     async fn download_artist(&self, id: String) -> Result<bool, Error> {
         self.progress.println("Getting Artist Albums")?;
-        let albums = self.client.media.get_artist_albums(&id).await?;
+        let albums = self
+            .client
+            .media
+            .get_artist_albums(&id, self.settings.include_singles)
+            .await?;
         for album in albums {
             self.download_list(ActionKind::Album, album.id.to_string())
                 .await?;
@@ -103,109 +187,547 @@ impl DownloadTask {
 
     async fn download_list(&self, kind: ActionKind, id: String) -> Result<bool, Error> {
         let url = format!("https://api.tidal.com/v1/{kind}s/{id}/items",);
-        let tracks = self
+        let items = self
             .client
             .media
-            .get_items::<ItemResponseItem<Track>>(&url, None, None)
+            .get_items::<ListItem>(&url, None, None)
             .await?;
-        for track in tracks {
-            let future = Box::pin(self.clone().download_track(track.item.id.to_string()));
-            match self.clone().worker_channel.send(future).await {
-                Ok(_) => continue,
-                Err(_) => return Err(anyhow!("Error Submitting download_track")),
+        let mut tracks = Vec::with_capacity(items.len());
+        for item in items {
+            match item {
+                ListItem::Track(track) => tracks.push(track),
+                ListItem::Video(video) if self.settings.include_videos => {
+                    let future = Box::pin(self.clone().download_video(video.id.to_string()));
+                    if self.worker_channel.send(future).await.is_err() {
+                        return Err(anyhow!("Error Submitting download_video"));
+                    }
+                }
+                ListItem::Video(_) => {}
+            }
+        }
+        tracks.sort_by_key(|t| t.track_number_on_playlist);
+
+        let playlist = match kind {
+            ActionKind::Playlist => Some(Arc::new(self.client.media.get_playlist(&id).await?)),
+            _ => None,
+        };
+        let slots: Vec<Option<PlaylistSlot>> = match self.list_name(kind, &id, &playlist).await? {
+            Some(name) => PlaylistFile::slots(
+                sanitize(name),
+                self.settings.playlist_formats.clone(),
+                self.settings.path_profile,
+                tracks.len(),
+            )
+            .into_iter()
+            .map(Some)
+            .collect(),
+            None => tracks.iter().map(|_| None).collect(),
+        };
+        for (index, (track, slot)) in tracks.into_iter().zip(slots).enumerate() {
+            let position = playlist.as_ref().map(|playlist| {
+                ListPosition::Playlist(PlaylistPosition {
+                    playlist: playlist.clone(),
+                    index,
+                })
+            });
+            let slot = match (position, slot) {
+                (None, None) => None,
+                (position, playlist) => Some(ListSlot { position, playlist }),
+            };
+            self.submit_track(track, slot).await?;
+        }
+        Ok(true)
+    }
+
+    // the name of the playlist files of a list, if the list gets any
+    async fn list_name(
+        &self,
+        kind: ActionKind,
+        id: &str,
+        playlist: &Option<Arc<Playlist>>,
+    ) -> Result<Option<String>, Error> {
+        if self.settings.playlist_formats.is_empty() {
+            return Ok(None);
+        }
+        match (kind, playlist) {
+            (ActionKind::Playlist, Some(playlist)) => Ok(Some(playlist.title.clone())),
+            (ActionKind::Album, _) if self.settings.album_playlists => {
+                let album = self.client.media.get_album(id.parse()?).await?;
+                Ok(Some(album.title.unwrap_or_else(|| id.to_string())))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn download_favorites(&self, collection: &str) -> Result<bool, Error> {
+        let collections: Vec<&str> = match collection {
+            "all" => FAVORITES.into_iter().filter(|c| *c != "all").collect(),
+            _ => vec![collection],
+        };
+        for collection in collections {
+            self.progress
+                .println(format!("Getting Favorite {collection}"))?;
+            let media = &self.client.media;
+            match collection {
+                "tracks" => {
+                    for track in media.get_favorites::<Track>(collection).await? {
+                        self.submit_track(track, None).await?;
+                    }
+                }
+                "videos" => {
+                    for video in media.get_favorites::<Video>(collection).await? {
+                        let future = Box::pin(self.clone().download_video(video.id.to_string()));
+                        if self.worker_channel.send(future).await.is_err() {
+                            return Err(anyhow!("Error Submitting download_video"));
+                        }
+                    }
+                }
+                "albums" => {
+                    for album in media.get_favorites::<Album>(collection).await? {
+                        self.download_list(ActionKind::Album, album.id.to_string())
+                            .await?;
+                    }
+                }
+                "artists" => {
+                    for artist in media.get_favorites::<Artist>(collection).await? {
+                        self.download_artist(artist.id.to_string()).await?;
+                    }
+                }
+                "playlists" => {
+                    for playlist in media.get_favorites::<Playlist>(collection).await? {
+                        self.download_list(ActionKind::Playlist, playlist.uuid)
+                            .await?;
+                    }
+                }
+                _ => return Err(anyhow!("Unknown favorites collection {collection}")),
             }
         }
         Ok(true)
     }
 
-    async fn download_track(self, id: String) -> Result<bool, Error> {
+    async fn download_track_radio(&self, id: String) -> Result<bool, Error> {
+        let track = self.client.media.get_track(&id).await?;
+        let mix = track
+            .mixes
+            .radio()
+            .ok_or_else(|| anyhow!("Track {} has no radio mix", track.get_info()))?;
+        self.download_mix(mix.to_string()).await
+    }
+
+    async fn download_artist_radio(&self, id: String) -> Result<bool, Error> {
+        let mix = self.client.media.get_artist_mix(&id).await?;
+        self.download_mix(mix).await
+    }
+
+    async fn download_mix(&self, id: String) -> Result<bool, Error> {
+        self.progress.println("Getting Mix Tracks")?;
+        let (mix, items) = try_join!(
+            self.client.media.get_mix(&id),
+            self.client.media.get_mix_items(&id)
+        )?;
+        let mix = Arc::new(mix);
+        let mut tracks = Vec::with_capacity(items.len());
+        for item in items {
+            match item {
+                ListItem::Track(track) => tracks.push(track),
+                ListItem::Video(video) if self.settings.include_videos => {
+                    let future = Box::pin(self.clone().download_video(video.id.to_string()));
+                    if self.worker_channel.send(future).await.is_err() {
+                        return Err(anyhow!("Error Submitting download_video"));
+                    }
+                }
+                ListItem::Video(_) => {}
+            }
+        }
+
+        let len = tracks.len();
+        let slots = PlaylistFile::slots(
+            sanitize(&mix.title),
+            self.settings.playlist_formats.clone(),
+            self.settings.path_profile,
+            len,
+        );
+        for (track, playlist) in tracks.into_iter().zip(slots) {
+            let position = MixPosition {
+                mix: mix.clone(),
+                index: playlist.index(),
+                len,
+            };
+            let slot = ListSlot {
+                position: Some(ListPosition::Mix(position)),
+                playlist: Some(playlist),
+            };
+            self.submit_track(track, Some(slot)).await?;
+        }
+        Ok(true)
+    }
+
+    // queues a track of a list, unless its file is already synced
+    async fn submit_track(&self, track: Track, slot: Option<ListSlot>) -> Result<(), Error> {
+        if let Some(path) = self.synced.get(&track.id) {
+            if let Some(mut playlist) = slot.and_then(|s| s.playlist) {
+                playlist.set(playlist_entry(&track, path.clone()));
+            }
+            return Ok(());
+        }
+        let future = Box::pin(self.clone().download_track(track.id.to_string(), slot));
+        match self.worker_channel.send(future).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(anyhow!("Error Submitting download_track")),
+        }
+    }
+
+    async fn download_track(self, id: String, slot: Option<ListSlot>) -> Result<bool, Error> {
         let track = self.client.media.get_track(&id).await?;
-        let path_str = self.get_path(&track).await?;
-        let download = Box::pin(self.clone().download_file(track, path_str));
+        let path_str = self.get_path(&track, slot.as_ref()).await?;
+        // a mix or playlist folder holds tracks of many albums, so it gets no album cover
+        let position = slot.as_ref().and_then(|s| s.position.as_ref());
+        let album_folder = self.list_template(position).is_none();
+        let playlist = slot.and_then(|s| s.playlist);
+        let download =
+            Box::pin(
+                self.clone()
+                    .download_file(track, path_str, playlist, album_folder),
+            );
+        match &self.dl_channel.send(download).await {
+            Ok(_) => Ok(true),
+            Err(_) => Err(anyhow!("Submitting Download Task failed")),
+        }
+    }
+
+    async fn download_video(self, id: String) -> Result<bool, Error> {
+        let video = self.client.media.get_video(&id).await?;
+        let path = self.get_video_path(&video).await?;
+        let download = Box::pin(self.clone().download_video_file(video, path));
         match &self.dl_channel.send(download).await {
             Ok(_) => Ok(true),
             Err(_) => Err(anyhow!("Submitting Download Task failed")),
         }
     }
 
-    async fn download_file(self, track: Track, mut path: PathBuf) -> Result<bool, anyhow::Error> {
+    async fn download_video_file(self, video: Video, path: PathBuf) -> Result<bool, Error> {
+        let info = video.get_info();
+        let pb = ProgressBar::new(self.progress.clone(), video.id);
+        let stream = self
+            .client
+            .media
+            .get_video_stream(video.id, self.settings.video_resolution)
+            .await?;
+        let path = self
+            .settings
+            .path_profile
+            .file_path(&path, stream.get_file_extension());
+        if path.exists() {
+            debug!("Path exists");
+            self.progress.println(format!("File Exists | {info}"))?;
+            self.summary.add_skipped();
+            return Ok(false);
+        }
+
+        tokio::fs::create_dir_all(
+            path.parent()
+                .ok_or_else(|| anyhow!("Parent Directory missing somehow"))?,
+        )
+        .await?;
+        // the HLS segments are joined in order into one file
+        let part_path = get_part_path(&path);
+        self.download_segments(&stream.segment_urls, &part_path, &pb, &info)
+            .await?;
+        tokio::fs::rename(&part_path, &path).await?;
+        self.summary.add_downloaded();
+        pb.println(format!("Download Complete | {info}"));
+
+        Ok(true)
+    }
+
+    async fn download_file(
+        self,
+        track: Track,
+        path: PathBuf,
+        mut playlist: Option<PlaylistSlot>,
+        album_folder: bool,
+    ) -> Result<bool, anyhow::Error> {
         let info = track.get_info();
         let pb = ProgressBar::new(self.progress.clone(), track.id);
-        let playback_manifest = self.client.media.get_stream_url(track.id).await?;
-        path.set_extension(
-            playback_manifest
-                .get_file_extension()
-                .expect("Unable to determine track file extension"),
-        );
+        let playback_manifest = self
+            .client
+            .media
+            .get_stream_url(track.id, self.settings.audio_quality)
+            .await?;
+        let extension = playback_manifest
+            .get_file_extension()
+            .expect("Unable to determine track file extension");
+        let path = self.settings.path_profile.file_path(&path, extension);
 
-        let stream_url = &playback_manifest.urls[0];
+        if self.settings.download_cover && album_folder {
+            // a missing cover shouldn't fail the track itself
+            if let Err(e) = self.write_cover(&track, &path).await {
+                pb.println(format!("Cover Download Failed | {info}: {e}"));
+            }
+        }
 
         if path.exists() {
             debug!("Path exists");
             self.progress
                 .println(format!("File Exists | {}", track.get_info()))?;
             // Exit early if the file already exists
+            self.summary.add_skipped();
+            self.summary.add_track_file(track.id, &path, false);
+            if let Some(playlist) = playlist.as_mut() {
+                playlist.set(playlist_entry(&track, path));
+            }
             return Ok(false);
         }
 
-        let response = CLIENT.get(stream_url).send().await?;
-        let total_size: u64 = response
-            .content_length()
-            .ok_or_else(|| anyhow!("Failed to get content length from {}", stream_url))?;
-        pb.start_download(total_size, &track);
-        debug!("Got Content Length: {total_size} for {}", track.get_info());
         tokio::fs::create_dir_all(
             path.parent()
                 .ok_or_else(|| anyhow!("Parent Directory missing somehow"))?,
         )
         .await?;
-        let file = File::create(path.clone()).await?;
-        // 1 MiB Write buffer to minimize syscalls for slow i/o
-        // Reduces write CPU time from 24% to 7%.
-        let mut writer = tokio::io::BufWriter::with_capacity(1024 * 1000 * 1000, file);
-        let mut downloaded: u64 = 0;
-        let mut stream = response.bytes_stream();
-        while let Some(item) = stream.next().await {
-            let chunk = item?;
-            downloaded = min(downloaded + (chunk.len() as u64), total_size);
-            pb.set_position(downloaded);
-            writer.write_all(&chunk).await?;
+        // download into a .part file first, so an interrupted download is never
+        // mistaken for a finished one by the exists check above
+        let part_path = get_part_path(&path);
+        // FLAC in MP4 is downloaded into its own part file, and remuxed into the FLAC part file
+        let download_path = match playback_manifest.is_flac_in_mp4() {
+            true => get_part_path(&path.with_extension("mp4")),
+            false => part_path.clone(),
+        };
+
+        let verify = self.settings.verify_downloads;
+        let mut attempt = 1;
+        loop {
+            // BTS manifests point to a single file, DASH manifests to an init segment
+            // followed by the media segments, which are joined in order into one file
+            match playback_manifest.urls.as_slice() {
+                [stream_url] => {
+                    self.download_stream(
+                        stream_url,
+                        &download_path,
+                        &playback_manifest,
+                        &pb,
+                        &track,
+                    )
+                    .await?
+                }
+                segment_urls => {
+                    self.download_segments(segment_urls, &download_path, &pb, &info)
+                        .await?
+                }
+            }
+            if download_path != part_path {
+                pb.set_message(format!("Remuxing to FLAC | {info}"));
+                let (src, dst) = (download_path.clone(), part_path.clone());
+                tokio::task::spawn_blocking(move || remux_flac(&src, &dst)).await??;
+                tokio::fs::remove_file(&download_path).await?;
+            }
+
+            pb.set_message(format!("Writing metadata | {info}"));
+            self.write_metadata(track.clone(), part_path.clone(), extension)
+                .await?;
+            if !verify {
+                break;
+            }
+
+            pb.set_message(format!("Verifying | {info}"));
+            let err = match verify_file(&part_path, &playback_manifest).await {
+                Ok(_) => break,
+                Err(e) => e,
+            };
+            let quarantined =
+                quarantine_file(&part_path, &path, track.id, &self.settings.quarantine_dir).await?;
+            self.summary
+                .add_quarantined(format!("{info} -> {}", quarantined.display()));
+            pb.println(format!("Verification Failed | {info}: {err}"));
+            if attempt >= VERIFY_ATTEMPTS {
+                return Err(anyhow!("Verification Failed | {info}: {err}"));
+            }
+            attempt += 1;
+        }
+        tokio::fs::rename(&part_path, &path).await?;
+        self.summary.add_downloaded();
+        self.summary.add_track_file(track.id, &path, true);
+        pb.println(format!("Download Complete | {info}"));
+        if let Some(playlist) = playlist.as_mut() {
+            playlist.set(playlist_entry(&track, path));
+        }
+
+        Ok(true)
+    }
+
+    async fn download_stream(
+        &self,
+        stream_url: &str,
+        part_path: &Path,
+        manifest: &PlaybackManifest,
+        pb: &ProgressBar,
+        track: &Track,
+    ) -> Result<(), Error> {
+        let info = track.get_info();
+        // every attempt resumes from the last byte written to the part file
+        retry_stream(&self.settings.stream_retry, pb, &info, || {
+            try_download_stream(stream_url, part_path, manifest, pb, &info)
+        })
+        .await
+    }
+
+    async fn download_segments(
+        &self,
+        segment_urls: &[String],
+        part_path: &Path,
+        pb: &ProgressBar,
+        info: &str,
+    ) -> Result<(), Error> {
+        pb.start_segments(segment_urls.len() as u64, info);
+        debug!("Got {} segments for {info}", segment_urls.len());
+        // the segments don't have a known total size to resume against,
+        // so a partial segmented download is always started over
+        let mut writer = open_part_file(part_path, false).await?;
+        for segment_url in segment_urls {
+            // segments are small, so a failed segment is fetched again as a whole
+            let segment = retry_stream(&self.settings.stream_retry, pb, info, || async {
+                let response = CLIENT.get(segment_url).send().await?.error_for_status()?;
+                Ok(response.bytes().await?)
+            })
+            .await?;
+            writer.write_all(&segment).await?;
+            pb.inc(1);
         }
 
         //flush buffer to disk;
         pb.set_message(format!("Writing to Disk | {info}"));
         writer.flush().await?;
+        Ok(())
+    }
 
-        pb.set_message(format!("Writing metadata | {info}"));
-        self.write_metadata(track, path).await?;
-        pb.println(format!("Download Complete | {info}"));
+    async fn write_cover(&self, track: &Track, path: &Path) -> Result<(), Error> {
+        let cover_id = match &track.album.cover {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let resolution = self.settings.cover_resolution;
+        let album_dir = path
+            .parent()
+            .ok_or_else(|| anyhow!("Parent Directory missing somehow"))?;
+        let cover_path = album_dir.join(&self.settings.cover_name);
+        // the first track of an album to get here writes the cover, every other one skips it
+        let claimed = self
+            .covers
+            .lock()
+            .expect("Cover lock poisoned")
+            .insert(cover_path.clone());
+        if !claimed || cover_path.exists() {
+            return Ok(());
+        }
 
-        Ok(true)
+        let res = async {
+            let cover = self
+                .client
+                .media
+                .get_cover_data(cover_id, resolution)
+                .await?;
+            tokio::fs::create_dir_all(album_dir).await?;
+            let part_path = get_part_path(&cover_path);
+            tokio::fs::write(&part_path, cover.data).await?;
+            tokio::fs::rename(&part_path, &cover_path).await?;
+            debug!("Cover written to {}", cover_path.display());
+            Ok(())
+        }
+        .await;
+        if res.is_err() {
+            // release the claim so another track of the album can try again
+            self.covers
+                .lock()
+                .expect("Cover lock poisoned")
+                .remove(&cover_path);
+        }
+        res
     }
 
-    async fn write_metadata(&self, track: Track, path: PathBuf) -> Result<(), Error> {
-        let fp = path.clone();
-        let mut tag = tokio::task::spawn_blocking(move || Tag::read_from_path(fp)).await??;
-        tag.set_vorbis("TITLE", vec![track.title]);
-        tag.set_vorbis("TRACKNUMBER", vec![track.track_number.to_string()]);
-        tag.set_vorbis("ARTIST", vec![track.artist.name]);
-        tag.set_vorbis("ALBUM", vec![track.album.title.unwrap_or_default()]);
-        tag.set_vorbis("COPYRIGHT", vec![track.copyright]);
-        tag.set_vorbis("ISRC", vec![track.isrc]);
-        if let Some(cover_id) = &track.album.cover {
-            let cover = self.client.media.get_cover_data(cover_id).await?;
-            tag.add_picture(cover.content_type, CoverFront, cover.data);
-        }
+    async fn write_metadata(
+        &self,
+        track: Track,
+        path: PathBuf,
+        extension: &str,
+    ) -> Result<(), Error> {
+        // the album embedded in the track is partial, the full one has the artist and totals
+        let album = self.client.media.get_album(track.album.id).await?;
+        let cover = match &album.cover {
+            Some(cover_id) => Some(
+                self.client
+                    .media
+                    .get_cover_data(cover_id, CoverResolution::R1280)
+                    .await?,
+            ),
+            None => None,
+        };
+        let tags = TrackTags {
+            artists: match track.artists.is_empty() {
+                true => vec![track.artist.name.clone()],
+                false => track.artists.iter().map(|a| a.name.clone()).collect(),
+            },
+            album_artist: match &album.artist {
+                Some(artist) => artist.name.clone(),
+                None => track.artist.name.clone(),
+            },
+            title: track.title,
+            version: track.version.filter(|v| !v.is_empty()),
+            album: album.title.unwrap_or_default(),
+            date: album.release_date,
+            track_number: track.track_number,
+            total_tracks: album.number_of_tracks,
+            disc_number: track.volume_number,
+            total_discs: album.number_of_volumes,
+            copyright: track.copyright,
+            isrc: track.isrc,
+            explicit: track.explicit,
+            track_id: track.id,
+            album_id: album.id,
+            cover,
+        };
 
-        tokio::task::spawn_blocking(move || tag.save()).await??;
+        let extension = extension.to_string();
+        tokio::task::spawn_blocking(move || write_tags(&path, &extension, &tags)).await??;
         info!("Metadata written to file");
         Ok(())
     }
 
-    async fn get_path(&self, track: &Track) -> Result<PathBuf, Error> {
-        let config = &CONFIG.read().await;
-        let mut dl_path = config.download_path.clone();
+    async fn get_video_path(&self, video: &Video) -> Result<PathBuf, Error> {
+        let artist = self
+            .client
+            .media
+            .get_artist(&video.artist.id.to_string())
+            .await?;
+        let album = match &video.album {
+            Some(album) => Some(self.client.media.get_album(album.id).await?),
+            None => None,
+        };
+        let dl_path = self.settings.video_path.render(&|name| {
+            video
+                .get_token(name, &self.settings)
+                .or_else(|| artist.get_token(name, &self.settings))
+                .or_else(|| {
+                    album
+                        .as_ref()
+                        .and_then(|a| a.get_token(name, &self.settings))
+                })
+        });
+
+        Ok(Path::new("").join(shellexpand::full(&dl_path)?.to_string()))
+    }
 
+    // the template a list places its tracks with in place of the download path, if any
+    fn list_template(&self, position: Option<&ListPosition>) -> Option<&Template> {
+        match position {
+            Some(ListPosition::Mix(_)) => Some(&self.settings.mix_path),
+            // playlists are placed with the download path unless they have a template of their own
+            Some(ListPosition::Playlist(_)) if !self.settings.playlist_path.is_empty() => {
+                Some(&self.settings.playlist_path)
+            }
+            _ => None,
+        }
+    }
+
+    async fn get_path(&self, track: &Track, slot: Option<&ListSlot>) -> Result<PathBuf, Error> {
         let album_id = &track.album.id;
         // The track artist can be different than the album artist
         // important to use the album artist for naming.
@@ -219,14 +741,239 @@ impl DownloadTask {
             self.client.media.get_artist(&artist_id)
         )?;
 
-        dl_path = artist.replace_path(&dl_path);
-        dl_path = album.replace_path(&dl_path);
-        dl_path = track.replace_path(&dl_path);
+        let position = slot.and_then(|s| s.position.as_ref());
+        let template = match self.list_template(position) {
+            Some(template) => Cow::Borrowed(template),
+            None => self
+                .settings
+                .download_paths
+                .template(album.album_type.as_deref(), &self.settings.download_path),
+        };
+        let dl_path = template.render(&|name| {
+            track
+                .get_token(name, &self.settings)
+                .or_else(|| album.get_token(name, &self.settings))
+                .or_else(|| artist.get_token(name, &self.settings))
+                .or_else(|| match position {
+                    Some(ListPosition::Mix(position)) => position.get_token(name, &self.settings),
+                    Some(ListPosition::Playlist(position)) => {
+                        position.get_token(name, &self.settings)
+                    }
+                    None => None,
+                })
+        });
 
         Ok(Path::new("").join(shellexpand::full(&dl_path)?.to_string()))
     }
 }
 
+async fn retry_stream<T, F, Fut>(
+    retry: &StreamRetry,
+    pb: &ProgressBar,
+    info: &str,
+    mut attempt: F,
+) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let policy = retry.policy();
+    let mut retries = 0;
+    loop {
+        let err = match attempt().await {
+            Ok(val) => return Ok(val),
+            Err(e) => e,
+        };
+        match policy.should_retry(retries) {
+            RetryDecision::Retry { execute_after } if is_retryable(&err) => {
+                retries += 1;
+                let wait = (execute_after - chrono::Utc::now())
+                    .to_std()
+                    .unwrap_or_default();
+                debug!("Stream failed for {info}, retrying in {wait:?}: {err}");
+                pb.set_message(format!(
+                    "Retrying ({retries}/{}) | {info}",
+                    policy.max_n_retries
+                ));
+                sleep(wait).await;
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+// client errors and local i/o errors won't resolve by reconnecting
+fn is_retryable(err: &Error) -> bool {
+    match err.downcast_ref::<reqwest::Error>() {
+        Some(e) => !e.status().is_some_and(|s| s.is_client_error()),
+        None => err.downcast_ref::<std::io::Error>().is_none(),
+    }
+}
+
+async fn try_download_stream(
+    stream_url: &str,
+    part_path: &Path,
+    manifest: &PlaybackManifest,
+    pb: &ProgressBar,
+    info: &str,
+) -> Result<(), Error> {
+    // resume from the end of a previously interrupted download
+    let mut offset = match tokio::fs::metadata(part_path).await {
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    };
+    let mut request = CLIENT.get(stream_url);
+    if offset > 0 {
+        debug!("Resuming download at byte {offset} for {info}");
+        request = request.header(RANGE, format!("bytes={offset}-"));
+    }
+    let mut response = request.send().await?;
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // the partial file doesn't match the stream anymore, start over
+        debug!("Range not satisfiable, restarting download for {info}");
+        response = CLIENT.get(stream_url).send().await?;
+    }
+    let response = response.error_for_status()?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        offset = 0;
+    }
+
+    let total_size: u64 = offset
+        + response
+            .content_length()
+            .ok_or_else(|| anyhow!("Failed to get content length from {}", stream_url))?;
+    pb.start_download(total_size, info);
+    pb.set_position(offset);
+    debug!("Got Content Length: {total_size} for {info}");
+
+    let mut writer = open_part_file(part_path, offset > 0).await?;
+    let mut decryptor = manifest.get_decryptor()?;
+    if let Some(decryptor) = decryptor.as_mut() {
+        decryptor.seek(offset);
+    }
+    let mut downloaded: u64 = offset;
+    let mut stream = response.bytes_stream();
+    while let Some(item) = stream.next().await {
+        let chunk = match item {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // keep everything received so far for the next attempt
+                writer.flush().await?;
+                return Err(e.into());
+            }
+        };
+        downloaded += chunk.len() as u64;
+        pb.set_position(min(downloaded, total_size));
+        match decryptor.as_mut() {
+            Some(decryptor) => {
+                let mut chunk = chunk.to_vec();
+                decryptor.apply(&mut chunk);
+                writer.write_all(&chunk).await?;
+            }
+            None => writer.write_all(&chunk).await?,
+        }
+    }
+
+    //flush buffer to disk;
+    pb.set_message(format!("Writing to Disk | {info}"));
+    writer.flush().await?;
+
+    // compare what actually ended up on disk against the server's content length
+    let written = tokio::fs::metadata(part_path).await?.len();
+    if written != total_size {
+        return Err(anyhow!(
+            "Download incomplete, wrote {written} of {total_size} bytes | {info}"
+        ));
+    }
+    Ok(())
+}
+
+async fn verify_file(path: &Path, manifest: &PlaybackManifest) -> Result<(), Error> {
+    match manifest.get_file_extension() {
+        Some("flac") => {
+            let path = path.to_path_buf();
+            tokio::task::spawn_blocking(move || verify_flac(&path)).await?
+        }
+        // only FLAC carries a checksum of its audio to verify against
+        _ => Ok(()),
+    }
+}
+
+// moves a download that failed verification aside, named after the track and the time it
+// failed so every failure of a track is kept
+async fn quarantine_file(
+    part_path: &Path,
+    path: &Path,
+    track_id: usize,
+    quarantine_dir: &str,
+) -> Result<PathBuf, Error> {
+    let quarantine_dir = PathBuf::from(quarantine_dir);
+    tokio::fs::create_dir_all(&quarantine_dir).await?;
+    let stem = path
+        .file_stem()
+        .ok_or_else(|| anyhow!("Download path has no file name"))?
+        .to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%3f");
+    let mut attempt = 0;
+    loop {
+        let suffix = match attempt {
+            0 => format!("-{stamp}{extension}"),
+            n => format!("-{stamp}-{n}{extension}"),
+        };
+        let prefix = format!("{track_id}-");
+        let mut stem = stem.to_string();
+        truncate(&mut stem, MAX_NAME_BYTES - prefix.len() - suffix.len());
+        let dest = quarantine_dir.join(format!("{prefix}{stem}{suffix}"));
+        if !dest.exists() {
+            move_file(part_path, &dest).await?;
+            return Ok(dest);
+        }
+        attempt += 1;
+    }
+}
+
+/// Moves a file, copying it over when `dest` is on a different filesystem than `src`.
+pub async fn move_file(src: &Path, dest: &Path) -> Result<(), Error> {
+    if tokio::fs::rename(src, dest).await.is_err() {
+        tokio::fs::copy(src, dest).await?;
+        tokio::fs::remove_file(src).await?;
+    }
+    Ok(())
+}
+
+fn playlist_entry(track: &Track, path: PathBuf) -> PlaylistEntry {
+    PlaylistEntry {
+        path,
+        duration: track.duration,
+        artist: track.artist.name.clone(),
+        title: track.title.clone(),
+    }
+}
+
+fn get_part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+async fn open_part_file(path: &Path, append: bool) -> Result<BufWriter<File>, Error> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .await?;
+    // 1 MiB Write buffer to minimize syscalls for slow i/o
+    // Reduces write CPU time from 24% to 7%.
+    // It also bounds what a killed download loses, as only the buffer is missing from the file
+    Ok(BufWriter::with_capacity(1024 * 1024, file))
+}
+
 fn setup_multi_progress(show_progress: bool, refresh_rate: u8) -> MultiProgress {
     let mp = MultiProgress::new();
     let draw_target = match show_progress {
@@ -236,3 +983,245 @@ fn setup_multi_progress(show_progress: bool, refresh_rate: u8) -> MultiProgress
     mp.set_draw_target(draw_target);
     mp
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn manifest() -> PlaybackManifest {
+        PlaybackManifest {
+            mime_type: "audio/flac".to_string(),
+            codecs: "flac".to_string(),
+            encryption_type: EncryptionType::None,
+            key_id: None,
+            urls: Vec::new(),
+        }
+    }
+
+    fn retry(max_retries: u32) -> StreamRetry {
+        StreamRetry {
+            max_retries,
+            min_interval_ms: 1,
+            max_interval_ms: 1,
+            backoff_exponent: 1,
+        }
+    }
+
+    fn progress_bar() -> ProgressBar {
+        ProgressBar::new(
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            0,
+        )
+    }
+
+    fn part_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tdl-{}-{name}.part", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn response(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut response =
+            format!("HTTP/1.1 {status}\r\nConnection: close\r\n{headers}\r\n").into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    async fn read_request(socket: &mut TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = socket.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        String::from_utf8_lossy(&request).to_lowercase()
+    }
+
+    // answers the nth connection with the nth response, repeating the last one, and closes
+    // it right after, which drops the body short if the response promised more
+    async fn serve(responses: Vec<Vec<u8>>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut socket).await;
+                let n = {
+                    let mut received = received.lock().unwrap();
+                    received.push(request);
+                    received.len()
+                };
+                let response = &responses[min(n, responses.len()) - 1];
+                socket.write_all(response).await.unwrap();
+                socket.shutdown().await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn resumes_a_dropped_stream_with_a_range_request() {
+        let source: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let cut = 4_000;
+        let length = format!("Content-Length: {}\r\n", source.len());
+        let rest = format!("Content-Length: {}\r\n", source.len() - cut);
+        let (url, requests) = serve(vec![
+            response("200 OK", &length, &source[..cut]),
+            response("206 Partial Content", &rest, &source[cut..]),
+        ])
+        .await;
+        let path = part_file("resume");
+        let pb = progress_bar();
+        let manifest = manifest();
+
+        retry_stream(&retry(3), &pb, "test", || {
+            try_download_stream(&url, &path, &manifest, &pb, "test")
+        })
+        .await
+        .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(!requests[0].contains("range:"));
+        assert!(requests[1].contains(&format!("range: bytes={cut}-\r\n")));
+        assert_eq!(std::fs::read(&path).unwrap(), source);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_a_part_file_that_was_never_flushed() {
+        let source: Vec<u8> = (0..3 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let path = part_file("killed");
+        // a download killed midway, with whatever was still buffered lost
+        {
+            let mut writer = open_part_file(&path, false).await.unwrap();
+            for chunk in source[..5 * 512 * 1024].chunks(64 * 1024) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            // wait for the writes the buffer handed to the file, without flushing the buffer
+            writer.get_mut().flush().await.unwrap();
+        }
+        let written = std::fs::metadata(&path).unwrap().len() as usize;
+        assert!((3 * 512 * 1024..5 * 512 * 1024).contains(&written));
+        assert_eq!(std::fs::read(&path).unwrap(), &source[..written]);
+
+        let rest = format!("Content-Length: {}\r\n", source.len() - written);
+        let (url, requests) = serve(vec![response(
+            "206 Partial Content",
+            &rest,
+            &source[written..],
+        )])
+        .await;
+        let pb = progress_bar();
+        try_download_stream(&url, &path, &manifest(), &pb, "test")
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].contains(&format!("range: bytes={written}-\r\n")));
+        assert_eq!(std::fs::read(&path).unwrap(), source);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let (url, requests) = serve(vec![response("200 OK", "Content-Length: 100\r\n", b"")]).await;
+        let path = part_file("give-up");
+        let pb = progress_bar();
+        let manifest = manifest();
+
+        let result = retry_stream(&retry(2), &pb, "test", || {
+            try_download_stream(&url, &path, &manifest, &pb, "test")
+        })
+        .await;
+
+        assert!(result.is_err());
+        // the first attempt and two retries
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (url, requests) = serve(vec![response(
+            "404 Not Found",
+            "Content-Length: 0\r\n",
+            b"",
+        )])
+        .await;
+        let path = part_file("not-found");
+        let pb = progress_bar();
+        let manifest = manifest();
+
+        let result = retry_stream(&retry(2), &pb, "test", || {
+            try_download_stream(&url, &path, &manifest, &pb, "test")
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn keeps_every_quarantined_copy() {
+        let dir = std::env::temp_dir().join(format!("tdl-quarantine-{}", std::process::id()));
+        let quarantine_dir = dir.join("quarantine");
+        std::fs::create_dir_all(&dir).unwrap();
+        let long = dir.join(format!(
+            "{}.flac",
+            "a".repeat(MAX_NAME_BYTES - ".flac.part".len())
+        ));
+        let mut quarantined = Vec::new();
+        for contents in ["first", "second"] {
+            let part = get_part_path(&long);
+            std::fs::write(&part, contents).unwrap();
+            let dest = quarantine_file(&part, &long, 123, quarantine_dir.to_str().unwrap())
+                .await
+                .unwrap();
+            let name = dest.file_name().unwrap().to_str().unwrap();
+            assert!(name.starts_with("123-aaa") && name.ends_with(".flac"));
+            assert!(name.len() <= MAX_NAME_BYTES);
+            quarantined.push(dest);
+        }
+        assert_ne!(quarantined[0], quarantined[1]);
+        assert_eq!(std::fs::read_to_string(&quarantined[0]).unwrap(), "first");
+        assert_eq!(std::fs::read_to_string(&quarantined[1]).unwrap(), "second");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn classifies_retryable_errors() {
+        // nothing listens on the port of a dropped listener, so connecting to it fails
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let connect = CLIENT
+            .get(format!("http://{addr}/"))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(is_retryable(&connect.into()));
+
+        let (url, _) = serve(vec![response(
+            "403 Forbidden",
+            "Content-Length: 0\r\n",
+            b"",
+        )])
+        .await;
+        let response = CLIENT.get(&url).send().await.unwrap();
+        let forbidden = response.error_for_status().unwrap_err();
+        assert!(!is_retryable(&forbidden.into()));
+
+        let io = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(!is_retryable(&io.into()));
+        assert!(is_retryable(&anyhow!("Download incomplete")));
+    }
+}
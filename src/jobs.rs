@@ -0,0 +1,95 @@
+use crate::api::models::AudioQuality;
+use crate::config::{track_tokens, Settings};
+use crate::template::Template;
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A URL to download, with the settings to download it with
+#[derive(Clone)]
+pub struct Job {
+    pub url: String,
+    pub settings: Arc<Settings>,
+    /// download the radio mix of a track or artist instead of the track or artist itself
+    pub radio: bool,
+    /// the files of tracks of the URL that are already on disk, listed in playlist files
+    /// in place of downloading the tracks again
+    pub synced: Arc<HashMap<usize, PathBuf>>,
+}
+
+impl Job {
+    pub fn new(url: String, settings: Arc<Settings>) -> Self {
+        Self {
+            url,
+            settings,
+            radio: false,
+            synced: Arc::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct JobFile {
+    jobs: Vec<JobEntry>,
+}
+
+/// An entry of a job file, where each setting that is set overrides the config for this URL
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct JobEntry {
+    pub url: String,
+    pub audio_quality: Option<AudioQuality>,
+    pub download_path: Option<Template>,
+    pub include_singles: Option<bool>,
+    pub download_cover: Option<bool>,
+    #[serde(default)]
+    pub radio: bool,
+}
+
+impl JobEntry {
+    pub fn into_job(self, base: &Settings) -> Job {
+        let mut settings = base.clone();
+        if let Some(audio_quality) = self.audio_quality {
+            settings.audio_quality = audio_quality;
+        }
+        if let Some(download_path) = self.download_path {
+            settings.download_path = download_path;
+            // the job's path applies to every track, whatever the type of its album
+            settings.download_paths = Default::default();
+        }
+        if let Some(include_singles) = self.include_singles {
+            settings.include_singles = include_singles;
+        }
+        if let Some(download_cover) = self.download_cover {
+            settings.download_cover = download_cover;
+        }
+        Job {
+            radio: self.radio,
+            ..Job::new(self.url, Arc::new(settings))
+        }
+    }
+}
+
+/// Reads the entries of a job file, parsed as JSON for a `.json` extension and TOML otherwise.
+pub fn read_job_file(path: &Path) -> Result<Vec<JobEntry>, Error> {
+    let contents = std::fs::read_to_string(path)?;
+    let file: JobFile = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&contents)?,
+        _ => toml::from_str(&contents)?,
+    };
+    if file.jobs.is_empty() {
+        return Err(anyhow!("No jobs in {}", path.display()));
+    }
+    let tokens = track_tokens();
+    for job in &file.jobs {
+        if let Some(download_path) = &job.download_path {
+            download_path
+                .check(&tokens)
+                .map_err(|e| anyhow!("Invalid download_path of {}: {e}", job.url))?;
+        }
+    }
+    Ok(file.jobs)
+}
@@ -1,6 +1,16 @@
 pub mod api;
+mod atoms;
 pub mod cli;
 pub mod config;
 pub mod download;
+pub mod jobs;
 pub mod login;
 pub mod models;
+pub mod normalize;
+pub mod picker;
+pub mod playlist;
+pub mod remux;
+pub mod sync;
+pub mod tags;
+pub mod template;
+pub mod verify;
@@ -78,7 +78,7 @@ pub async fn login_config(client: AuthClient) -> Result<TidalClient, Error> {
         debug!("Attempting to validate access token");
         if client.verify_access_token(access_token).await? {
             println!("Access Token Valid");
-            return Ok(TidalClient::new(&*config));
+            return Ok(TidalClient::new(&config));
         }
     }
 
@@ -92,6 +92,8 @@ pub async fn login_config(client: AuthClient) -> Result<TidalClient, Error> {
             let mut config = CONFIG.write().await;
             config.login_key.expires_after = Some(refresh.expires_in + now);
             config.login_key.access_token = Some(refresh.access_token);
+            // logins from before the user id was stored get it on their next refresh
+            config.login_key.user_id = Some(refresh.user.user_id);
             debug!("Attempting to save access token");
             config.save().expect("Failed to Save Access Token");
             println!("Access Token Refreshed with Refresh Token");
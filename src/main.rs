@@ -1,10 +1,21 @@
 use clap::ArgMatches;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use tabled::TableIteratorExt;
 use tdl::api::auth::AuthClient;
-use tdl::cli::{cli, parse_config_flags};
+use tdl::api::models::{Album, Artist, Playlist, SearchResult, Track, Video};
+use tdl::api::TidalClient;
+use tdl::cli::{cli, get_targets, parse_config_flags};
 use tdl::config::CONFIG;
 use tdl::download::dispatch_downloads;
 use tdl::download::ReceiveChannel;
+use tdl::jobs::{read_job_file, Job};
 use tdl::login::*;
+use tdl::models::{Action, ActionKind, RunSummary};
+use tdl::picker::pick;
+use tdl::sync::{get_source_tracks, Removal, SyncState};
 
 use env_logger::Env;
 use futures::future::join_all;
@@ -25,6 +36,8 @@ async fn main() {
     let matches = cli().get_matches();
     match matches.subcommand() {
         Some(("get", get_matches)) => get(get_matches).await,
+        Some(("search", search_matches)) => search(search_matches).await,
+        Some(("sync", sync_matches)) => sync(sync_matches).await,
         Some(("login", _)) => {
             login().await;
         }
@@ -37,22 +50,227 @@ async fn get(matches: &ArgMatches) {
     let client = login().await;
 
     parse_config_flags(matches).await;
-    if let Some(urls) = matches.get_many::<String>("URL") {
-        let url: Vec<String> = urls.map(|i| i.to_owned()).collect();
-        debug!("Collected args");
-        let (handles, download, worker) = dispatch_downloads(url, client)
-            .await
-            .expect("Unable to dispatch download thread");
-        let config = CONFIG.read().await;
-        join!(
-            join_all(handles),
-            consume_channel(download, config.downloads.into(),),
-            consume_channel(worker, config.workers.into())
-        );
+    // without any link the arguments are a search query to pick the downloads from
+    let args: Vec<String> = matches
+        .get_many::<String>("URL")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let is_query = !matches.contains_id("input-file")
+        && !matches.contains_id("job")
+        && !args.iter().any(|u| *u == "-" || Action::looks_like_link(u));
+    let url = match is_query {
+        true => match run_search(&client, matches, &args.join(" "), SearchOutput::Pick).await {
+            Ok(picks) => picks,
+            Err(e) => return eprintln!("Error searching TIDAL: {e}"),
+        },
+        false => match get_targets(matches) {
+            Ok(url) => url,
+            Err(e) => return eprintln!("Error reading URLs: {e}"),
+        },
+    };
+    debug!("Collected args");
+    let settings = Arc::new(CONFIG.read().await.clone());
+    let radio = matches.contains_id("radio");
+    let mut jobs: Vec<Job> = url
+        .into_iter()
+        .map(|url| Job {
+            radio,
+            ..Job::new(url, settings.clone())
+        })
+        .collect();
+    if let Some(path) = matches.get_one::<PathBuf>("job") {
+        match read_job_file(path) {
+            Ok(entries) => jobs.extend(entries.into_iter().map(|e| e.into_job(&settings))),
+            Err(e) => return eprintln!("Error reading job file {}: {e}", path.display()),
+        }
+    }
+    if jobs.is_empty() {
+        return println!("Nothing selected to download");
+    }
+    download(jobs, client).await;
+}
+
+async fn search(matches: &ArgMatches) {
+    let client = login().await;
+
+    let query = matches
+        .get_one::<String>("QUERY")
+        .expect("QUERY is required");
+    let output = match (
+        matches.contains_id("interactive"),
+        matches.contains_id("json"),
+    ) {
+        (true, _) => SearchOutput::Pick,
+        (false, true) => SearchOutput::Json,
+        (false, false) => SearchOutput::Table,
+    };
+    match run_search(&client, matches, query, output).await {
+        Ok(picks) if !picks.is_empty() => {
+            let settings = Arc::new(CONFIG.read().await.clone());
+            let jobs = picks
+                .into_iter()
+                .map(|url| Job::new(url, settings.clone()))
+                .collect();
+            download(jobs, client).await;
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Error searching TIDAL: {e}"),
+    }
+}
+
+async fn sync(matches: &ArgMatches) {
+    let client = login().await;
+
+    let url = matches.get_one::<String>("URL").expect("URL is required");
+    let action = match Action::from_str(url) {
+        Ok(action) => action,
+        Err(e) => return eprintln!("Invalid URL {url}: {e}"),
+    };
+    let mut settings = CONFIG.read().await.clone();
+    // only the tracks of a source are synced
+    settings.include_videos = false;
+    let mut state = match SyncState::load(&settings.sync_dir, &action, url) {
+        Ok(state) => state,
+        Err(e) => return eprintln!("Error reading sync state of {url}: {e}"),
+    };
+    let tracks = match get_source_tracks(&client, &action).await {
+        Ok(tracks) => tracks,
+        Err(e) => return eprintln!("Error getting tracks of {url}: {e}"),
+    };
+    let diff = state.diff(tracks);
+    let removal = match (
+        matches.contains_id("delete"),
+        matches.get_one::<PathBuf>("move-to"),
+    ) {
+        (true, _) => Removal::Delete,
+        (false, Some(dir)) => Removal::MoveTo(dir.to_owned()),
+        (false, None) => Removal::Keep,
+    };
+
+    let mut failed = Vec::new();
+    if !diff.added.is_empty() || !diff.removed.is_empty() {
+        // the whole source is downloaded so tracks keep their place in the list and its
+        // playlist files, with the tracks already on disk listed instead of downloaded
+        let job = Job {
+            synced: Arc::new(diff.kept),
+            ..Job::new(url.clone(), Arc::new(settings))
+        };
+        let summary = download(vec![job], client).await;
+        failed = state.record(diff.added.clone(), &summary.track_files());
+    }
+    let mut removed = Vec::new();
+    for (id, synced) in &diff.removed {
+        match state.remove(*id, &removal).await {
+            Ok(outcome) => removed.push(format!("{} ({outcome})", synced.title)),
+            Err(e) => eprintln!("Unable to remove {}: {e}", synced.path.display()),
+        }
+    }
+    if let Err(e) = state.save() {
+        eprintln!("Error saving sync state of {url}: {e}");
+    }
+
+    let added: Vec<&Track> = diff
+        .added
+        .iter()
+        .filter(|t| !failed.iter().any(|f| f.id == t.id))
+        .collect();
+    println!("Added {} track(s)", added.len());
+    for track in added {
+        println!("  + {} - {}", track.artist.name, track.title);
+    }
+    if !failed.is_empty() {
+        println!("Failed {} track(s), retried on the next sync", failed.len());
+        for track in &failed {
+            println!("  ! {} - {}", track.artist.name, track.title);
+        }
+    }
+    println!("Removed {} track(s)", removed.len());
+    for entry in removed {
+        println!("  - {entry}");
+    }
+}
+
+async fn download(jobs: Vec<Job>, client: TidalClient) -> Arc<RunSummary> {
+    let (handles, download, worker, summary) = dispatch_downloads(jobs, client)
+        .await
+        .expect("Unable to dispatch download thread");
+    let config = CONFIG.read().await;
+    join!(
+        join_all(handles),
+        consume_channel(download, config.downloads.into(), &summary),
+        consume_channel(worker, config.workers.into(), &summary)
+    );
+    println!("{summary}");
+    summary
+}
+
+enum SearchOutput {
+    Table,
+    Json,
+    Pick,
+}
+
+/// Searches for the type of content set by the `type` flag, and returns the URLs picked by
+/// the user when the output is [`SearchOutput::Pick`].
+async fn run_search(
+    client: &TidalClient,
+    matches: &ArgMatches,
+    query: &str,
+    output: SearchOutput,
+) -> Result<Vec<String>, anyhow::Error> {
+    let kind = matches
+        .get_one::<String>("type")
+        .expect("type has a default");
+    let limit = *matches
+        .get_one::<usize>("limit")
+        .expect("limit has a default");
+    match ActionKind::from_str(kind)? {
+        ActionKind::Track => search_content::<Track>(client, "tracks", query, limit, output).await,
+        ActionKind::Album => search_content::<Album>(client, "albums", query, limit, output).await,
+        ActionKind::Artist => {
+            search_content::<Artist>(client, "artists", query, limit, output).await
+        }
+        ActionKind::Playlist => {
+            search_content::<Playlist>(client, "playlists", query, limit, output).await
+        }
+        ActionKind::Video => search_content::<Video>(client, "videos", query, limit, output).await,
+        ActionKind::Mix | ActionKind::Favorites => Err(anyhow::anyhow!(
+            "Only tracks, albums, artists, playlists and videos can be searched for"
+        )),
+    }
+}
+
+async fn search_content<T>(
+    client: &TidalClient,
+    url: &str,
+    query: &str,
+    limit: usize,
+    output: SearchOutput,
+) -> Result<Vec<String>, anyhow::Error>
+where
+    T: DeserializeOwned + Serialize + Into<SearchResult>,
+{
+    let results = client
+        .search
+        .search_content::<T>(url, query, Some(limit))
+        .await?;
+    if let SearchOutput::Json = output {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(Vec::new());
+    }
+    let rows: Vec<SearchResult> = results.into_iter().map(Into::into).collect();
+    match output {
+        SearchOutput::Pick => tokio::task::spawn_blocking(|| pick(rows)).await?,
+        _ => {
+            println!("{}", rows.table());
+            Ok(Vec::new())
+        }
     }
 }
 
-async fn consume_channel(channel: ReceiveChannel, concurrency: usize) {
+async fn consume_channel(channel: ReceiveChannel, concurrency: usize, summary: &RunSummary) {
     //The channel receives an unexecuted future as a stream
     ReceiverStream::new(channel)
         //execute that future in a greenthread
@@ -64,10 +282,16 @@ async fn consume_channel(channel: ReceiveChannel, concurrency: usize) {
                 Ok(l) => match l {
                     Ok(_) => {}
                     //if the task failed
-                    Err(f) => eprintln!("{f}"),
+                    Err(f) => {
+                        summary.add_failed();
+                        eprintln!("{f}")
+                    }
                 },
                 // if we failed to launch the task
-                Err(e) => eprintln!("{e}"),
+                Err(e) => {
+                    summary.add_failed();
+                    eprintln!("{e}")
+                }
             }
         })
         .await;
@@ -2,8 +2,11 @@ use anyhow::Error;
 
 use indicatif::{MultiProgress, ProgressStyle};
 
-use crate::api::models::Track;
+use std::collections::HashMap;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::{fmt, str::FromStr};
 
 pub struct ProgressBar(indicatif::ProgressBar);
@@ -21,12 +24,20 @@ impl ProgressBar {
         Self(pb)
     }
 
-    pub fn start_download(&self, length: u64, track: &Track) {
+    pub fn start_download(&self, length: u64, info: &str) {
         self.set_length(length);
         self.set_style(ProgressStyle::default_bar()
                         .template("{wide_msg}\n{spinner:.green} [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec:4}, ETA: {eta:2})").expect("Progress Bar Template is invalid")
                         .progress_chars("#>-"));
-        self.set_message(format!("Downloading File | {}", track.get_info()));
+        self.set_message(format!("Downloading File | {info}"));
+    }
+
+    pub fn start_segments(&self, segments: u64, info: &str) {
+        self.set_length(segments);
+        self.set_style(ProgressStyle::default_bar()
+                        .template("{wide_msg}\n{spinner:.green} [{wide_bar:.cyan/blue}] {pos}/{len} segments (ETA: {eta:2})").expect("Progress Bar Template is invalid")
+                        .progress_chars("#>-"));
+        self.set_message(format!("Downloading File | {info}"));
     }
 }
 
@@ -38,28 +49,234 @@ impl Deref for ProgressBar {
     }
 }
 
+/// Totals of a run, printed once every download has finished
+#[derive(Default)]
+pub struct RunSummary {
+    downloaded: AtomicUsize,
+    skipped: AtomicUsize,
+    failed: AtomicUsize,
+    quarantined: Mutex<Vec<String>>,
+    // where each track that was downloaded or already existed is on disk, by track id
+    track_files: Mutex<HashMap<usize, TrackFile>>,
+}
+
+/// The file of a track of a run
+#[derive(Clone, Debug)]
+pub struct TrackFile {
+    pub path: PathBuf,
+    /// whether the run wrote the file, rather than finding it already on disk
+    pub created: bool,
+}
+
+impl RunSummary {
+    pub fn add_downloaded(&self) {
+        self.downloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_quarantined(&self, entry: String) {
+        self.quarantined
+            .lock()
+            .expect("Run summary lock poisoned")
+            .push(entry);
+    }
+
+    pub fn add_track_file(&self, id: usize, path: &Path, created: bool) {
+        let file = TrackFile {
+            path: path.to_path_buf(),
+            created,
+        };
+        self.track_files
+            .lock()
+            .expect("Run summary lock poisoned")
+            .insert(id, file);
+    }
+
+    /// The files of every track that was downloaded or already existed, by track id
+    pub fn track_files(&self) -> HashMap<usize, TrackFile> {
+        self.track_files
+            .lock()
+            .expect("Run summary lock poisoned")
+            .clone()
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "Downloaded: {} | Skipped: {} | Failed: {}",
+            self.downloaded.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed)
+        )?;
+        let quarantined = self.quarantined.lock().expect("Run summary lock poisoned");
+        if !quarantined.is_empty() {
+            write!(
+                fmt,
+                "\nQuarantined {} file(s) that failed verification:",
+                quarantined.len()
+            )?;
+            for entry in quarantined.iter() {
+                write!(fmt, "\n  {entry}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct Action {
     pub kind: ActionKind,
     pub id: String,
 }
+
+/// Reasons a link can't be turned into an [`Action`]
+#[derive(Debug)]
+pub enum ActionError {
+    Empty,
+    NotTidal(String),
+    NoContent(String),
+    MissingId(ActionKind),
+    InvalidId(ActionKind, String),
+}
+
+impl std::error::Error for ActionError {}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ActionError::Empty => write!(fmt, "Empty link"),
+            ActionError::NotTidal(host) => write!(fmt, "'{host}' is not a TIDAL link"),
+            ActionError::NoContent(link) => {
+                write!(
+                    fmt,
+                    "'{link}' does not link to a track, album, artist, playlist, video or mix"
+                )
+            }
+            ActionError::MissingId(kind) => write!(fmt, "The {kind} link is missing its id"),
+            ActionError::InvalidId(kind, id) => write!(fmt, "'{id}' is not a valid {kind} id"),
+        }
+    }
+}
+
+/// Parses the forms content is linked to by TIDAL:
+///
+/// - web links, such as `https://tidal.com/browse/album/123`, `https://listen.tidal.com/album/123`
+///   or `tidal.com/track/123/`, ignoring any query string and trailing path segments
+/// - app URIs, such as `tidal://album/123`
+/// - bare ids, where a number is taken as a track and a UUID as a playlist
+/// - `favorites:<collection>` for the favorites of the logged in user
 impl FromStr for Action {
-    type Err = Error;
+    type Err = ActionError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let url_parts: Vec<&str> = s.split('/').collect();
-        let [kind, id]: [_; 2] = url_parts[url_parts.len() - 2..].try_into()?;
-        Ok(Self {
-            kind: ActionKind::from_str(kind)?,
-            id: id.into(),
-        })
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ActionError::Empty);
+        }
+        // the collection of the logged in user, e.g. favorites:albums
+        if let Some(collection) = s.strip_prefix("favorites") {
+            let collection = match collection.strip_prefix(':') {
+                Some(collection) => collection,
+                None if collection.is_empty() => "all",
+                None => return Err(ActionError::NoContent(s.to_string())),
+            };
+            return Action::new(ActionKind::Favorites, collection);
+        }
+        if !s.contains('/') {
+            return Action::from_id(s);
+        }
+
+        let (scheme, rest) = s.split_once("://").unwrap_or(("https", s));
+        let rest = rest.split(['?', '#']).next().unwrap_or_default();
+        let mut segments = rest.split('/').filter(|p| !p.is_empty());
+        match scheme.to_ascii_lowercase().as_str() {
+            "tidal" => {}
+            "http" | "https" => {
+                let host = segments.next().unwrap_or_default().to_ascii_lowercase();
+                let host = host.split(':').next().unwrap_or_default();
+                if host != "tidal.com" && !host.ends_with(".tidal.com") {
+                    return Err(ActionError::NotTidal(host.to_string()));
+                }
+            }
+            _ => return Err(ActionError::NotTidal(s.to_string())),
+        }
+
+        // the first content type in the path, followed by its id
+        let mut segments = segments.skip_while(|p| ActionKind::from_str(p).is_err());
+        let kind = match segments.next() {
+            Some(kind) => ActionKind::from_str(kind).expect("skipped to a content type"),
+            None => return Err(ActionError::NoContent(s.to_string())),
+        };
+        match segments.next() {
+            Some(id) => Action::new(kind, id),
+            None => Err(ActionError::MissingId(kind)),
+        }
     }
 }
+
+impl Action {
+    fn new(kind: ActionKind, id: &str) -> Result<Self, ActionError> {
+        let valid = match kind {
+            ActionKind::Playlist => is_uuid(id),
+            ActionKind::Mix => !id.is_empty() && id.bytes().all(|b| b.is_ascii_alphanumeric()),
+            ActionKind::Favorites => FAVORITES.contains(&id),
+            _ => !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()),
+        };
+        match valid {
+            true => Ok(Self {
+                kind,
+                id: id.to_string(),
+            }),
+            false => Err(ActionError::InvalidId(kind, id.to_string())),
+        }
+    }
+
+    /// Whether `s` is meant as a link, valid or not, rather than as words to search for.
+    pub fn looks_like_link(s: &str) -> bool {
+        let s = s.trim();
+        s.contains("://")
+            || s.to_ascii_lowercase().contains("tidal.com")
+            || s.starts_with("favorites")
+            || (!s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+            || is_uuid(s)
+    }
+
+    fn from_id(id: &str) -> Result<Self, ActionError> {
+        match is_uuid(id) {
+            true => Action::new(ActionKind::Playlist, id),
+            false => Action::new(ActionKind::Track, id),
+        }
+    }
+}
+
+/// The collections of `favorites:<collection>`, where `all` is every other collection
+pub const FAVORITES: [&str; 6] = ["tracks", "albums", "artists", "playlists", "videos", "all"];
+
+fn is_uuid(id: &str) -> bool {
+    let groups: Vec<&str> = id.split('-').collect();
+    groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12])
+        && groups
+            .iter()
+            .all(|g| g.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
 #[derive(Debug)]
 pub enum ActionKind {
     Track,
     Album,
     Artist,
     Playlist,
+    Video,
+    Mix,
+    Favorites,
 }
 impl FromStr for ActionKind {
     type Err = Error;
@@ -69,6 +286,9 @@ impl FromStr for ActionKind {
             "album" => Ok(ActionKind::Album),
             "artist" => Ok(ActionKind::Artist),
             "playlist" => Ok(ActionKind::Playlist),
+            "video" => Ok(ActionKind::Video),
+            "mix" => Ok(ActionKind::Mix),
+            "favorites" => Ok(ActionKind::Favorites),
             _ => Err(Error::msg("No action kind for type")),
         }
     }
@@ -81,8 +301,113 @@ impl fmt::Display for ActionKind {
             ActionKind::Album => "album",
             ActionKind::Artist => "artist",
             ActionKind::Playlist => "playlist",
+            ActionKind::Video => "video",
+            ActionKind::Mix => "mix",
+            ActionKind::Favorites => "favorites",
         };
         fmt.write_str(str)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "0c3e8b8e-4d1f-4b6a-9f44-6a2c1b0a3e11";
+
+    fn parse(link: &str) -> (String, String) {
+        let action = Action::from_str(link).unwrap();
+        (action.kind.to_string(), action.id)
+    }
+
+    fn kind(kind: &str, id: &str) -> (String, String) {
+        (kind.to_string(), id.to_string())
+    }
+
+    #[test]
+    fn parses_web_links() {
+        assert_eq!(
+            parse("https://tidal.com/browse/album/123"),
+            kind("album", "123")
+        );
+        assert_eq!(parse("https://tidal.com/track/123/"), kind("track", "123"));
+        assert_eq!(
+            parse("https://tidal.com/browse/track/1?u"),
+            kind("track", "1")
+        );
+        assert_eq!(
+            parse("https://listen.tidal.com/artist/42"),
+            kind("artist", "42")
+        );
+        assert_eq!(
+            parse("https://tidal.com/browse/track/1/extra"),
+            kind("track", "1")
+        );
+        assert_eq!(parse("tidal.com/video/7"), kind("video", "7"));
+        assert_eq!(
+            parse(&format!("https://tidal.com/browse/playlist/{UUID}")),
+            kind("playlist", UUID)
+        );
+        assert_eq!(
+            parse("https://tidal.com/browse/mix/0123abc"),
+            kind("mix", "0123abc")
+        );
+    }
+
+    #[test]
+    fn parses_app_uris_and_bare_ids() {
+        assert_eq!(parse("tidal://album/123"), kind("album", "123"));
+        assert_eq!(parse("tidal://track/5/"), kind("track", "5"));
+        assert_eq!(parse(" 123 "), kind("track", "123"));
+        assert_eq!(parse(UUID), kind("playlist", UUID));
+    }
+
+    #[test]
+    fn parses_favorites() {
+        assert_eq!(parse("favorites"), kind("favorites", "all"));
+        assert_eq!(parse("favorites:albums"), kind("favorites", "albums"));
+        assert_eq!(parse("favorites:all"), kind("favorites", "all"));
+        assert!(matches!(
+            Action::from_str("favorites:songs"),
+            Err(ActionError::InvalidId(ActionKind::Favorites, id)) if id == "songs"
+        ));
+    }
+
+    #[test]
+    fn reports_why_a_link_is_invalid() {
+        assert!(matches!(Action::from_str("  "), Err(ActionError::Empty)));
+        assert!(matches!(
+            Action::from_str("https://example.com/album/1"),
+            Err(ActionError::NotTidal(host)) if host == "example.com"
+        ));
+        assert!(matches!(
+            Action::from_str("spotify://album/1"),
+            Err(ActionError::NotTidal(_))
+        ));
+        assert!(matches!(
+            Action::from_str("https://tidal.com/browse/genre/1"),
+            Err(ActionError::NoContent(_))
+        ));
+        assert!(matches!(
+            Action::from_str("favoritesalbums"),
+            Err(ActionError::NoContent(_))
+        ));
+        assert!(matches!(
+            Action::from_str("https://tidal.com/browse/album/"),
+            Err(ActionError::MissingId(ActionKind::Album))
+        ));
+        assert!(matches!(
+            Action::from_str("https://tidal.com/browse/album/abc"),
+            Err(ActionError::InvalidId(ActionKind::Album, id)) if id == "abc"
+        ));
+        assert!(matches!(
+            Action::from_str("tidal://playlist/123"),
+            Err(ActionError::InvalidId(ActionKind::Playlist, _))
+        ));
+        assert!(matches!(
+            Action::from_str("daft"),
+            Err(ActionError::InvalidId(ActionKind::Track, _))
+        ));
+    }
+}
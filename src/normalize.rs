@@ -0,0 +1,233 @@
+use deunicode::deunicode;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+/// The longest file or folder name most filesystems allow, in bytes
+pub const MAX_NAME_BYTES: usize = 255;
+// room left in file names for the .part suffix of downloads in progress
+const PART_SUFFIX_BYTES: usize = ".part".len();
+
+#[derive(SerializeDisplay, DeserializeFromStr, Clone, Debug, Copy, PartialEq, Eq)]
+/// Rules for the file and folder names of downloads, for the filesystem they are written to
+pub enum PathProfile {
+    Posix,
+    /// Windows and FAT32 drives
+    Windows,
+    /// SMB shares, which are case insensitive and can't hold Windows' special characters
+    Smb,
+    /// POSIX names transliterated to ASCII
+    Ascii,
+}
+
+impl fmt::Display for PathProfile {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let str = match self {
+            PathProfile::Posix => "posix",
+            PathProfile::Windows => "windows",
+            PathProfile::Smb => "smb",
+            PathProfile::Ascii => "ascii",
+        };
+        fmt.write_str(str)?;
+        Ok(())
+    }
+}
+
+impl FromStr for PathProfile {
+    type Err = String;
+    fn from_str(input: &str) -> Result<PathProfile, Self::Err> {
+        match input {
+            "posix" => Ok(PathProfile::Posix),
+            "windows" | "fat32" => Ok(PathProfile::Windows),
+            "smb" => Ok(PathProfile::Smb),
+            "ascii" => Ok(PathProfile::Ascii),
+            _ => Err("Error".to_string()),
+        }
+    }
+}
+
+impl PathProfile {
+    /// The path of a download without an extension, with `extension` appended to the file name
+    /// and every folder and file name that doesn't exist yet made safe for the profile.
+    ///
+    /// Names are NFC normalized and shortened to fit the filesystem, without cutting into the
+    /// extension. A new name that matches an existing one in the same folder, once normalized
+    /// and on case insensitive profiles ignoring case, is replaced by the existing name.
+    pub fn file_path(self, path: &Path, extension: &str) -> PathBuf {
+        let mut components = path.components().peekable();
+        let mut out = PathBuf::new();
+        while let Some(component) = components.next() {
+            let last = components.peek().is_none();
+            let name = match component {
+                Component::Normal(name) if last => {
+                    self.file_name(&name.to_string_lossy(), extension)
+                }
+                Component::Normal(name) if !out.join(name).exists() => {
+                    self.name(&name.to_string_lossy(), MAX_NAME_BYTES)
+                }
+                other => {
+                    out.push(other);
+                    continue;
+                }
+            };
+            let name = self.existing_name(&out, &name).unwrap_or(name);
+            out.push(name);
+        }
+        out
+    }
+
+    fn file_name(self, stem: &str, extension: &str) -> String {
+        let max = MAX_NAME_BYTES - PART_SUFFIX_BYTES - extension.len() - 1;
+        format!("{}.{extension}", self.name(stem, max))
+    }
+
+    fn name(self, name: &str, max_bytes: usize) -> String {
+        let mut name: String = match self {
+            PathProfile::Ascii => deunicode(name),
+            _ => name.nfc().collect(),
+        };
+        if matches!(self, PathProfile::Windows | PathProfile::Smb) {
+            name = name
+                .chars()
+                .map(|c| match c {
+                    '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+                    c if c.is_control() => '_',
+                    c => c,
+                })
+                .collect();
+        }
+        if self == PathProfile::Windows {
+            // the device name is the part before the first dot, so that's where it's changed
+            let stem = name.find('.').unwrap_or(name.len());
+            if is_reserved(&name[..stem]) {
+                name.insert(stem, '_');
+            }
+        }
+        truncate(&mut name, max_bytes);
+        if matches!(self, PathProfile::Windows | PathProfile::Smb) {
+            // trailing dots and spaces are dropped by Windows, so the name wouldn't be found again
+            name.truncate(name.trim_end_matches(['.', ' ']).len());
+        }
+        if name.is_empty() {
+            name.push('_');
+        }
+        name
+    }
+
+    // a name in `dir` that is the same as `name` for the profile
+    fn existing_name(self, dir: &Path, name: &str) -> Option<String> {
+        let key = self.compare_key(name);
+        std::fs::read_dir(dir)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.file_name())
+            .filter_map(|existing| existing.to_str().map(str::to_string))
+            .find(|existing| self.compare_key(existing) == key)
+    }
+
+    fn compare_key(self, name: &str) -> String {
+        let name: String = name.nfc().collect();
+        match self {
+            PathProfile::Windows | PathProfile::Smb => name.to_lowercase(),
+            PathProfile::Posix | PathProfile::Ascii => name,
+        }
+    }
+}
+
+/// Shortens a string to at most `max` bytes, on a character boundary.
+pub fn truncate(name: &mut String, max: usize) {
+    if name.len() <= max {
+        return;
+    }
+    let mut end = max;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    name.truncate(end);
+}
+
+// device names Windows reserves, which it also ignores trailing spaces of
+fn is_reserved(stem: &str) -> bool {
+    let stem = stem.trim_end_matches(' ').to_uppercase();
+    matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.ends_with(|c: char| c.is_ascii_digit() && c != '0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserved_names_get_a_suffix_on_their_stem() {
+        let windows = PathProfile::Windows;
+        assert_eq!(windows.name("CON", MAX_NAME_BYTES), "CON_");
+        assert_eq!(windows.name("con", MAX_NAME_BYTES), "con_");
+        assert_eq!(windows.name("CON.Live", MAX_NAME_BYTES), "CON_.Live");
+        assert_eq!(windows.name("Aux.tar.gz", MAX_NAME_BYTES), "Aux_.tar.gz");
+        assert_eq!(windows.name("NUL .txt", MAX_NAME_BYTES), "NUL _.txt");
+        assert_eq!(windows.name("COM1", MAX_NAME_BYTES), "COM1_");
+        assert_eq!(windows.name("LPT9.Remix", MAX_NAME_BYTES), "LPT9_.Remix");
+        assert_eq!(windows.file_name("PRN.Live", "flac"), "PRN_.Live.flac");
+
+        assert_eq!(windows.name("COM0", MAX_NAME_BYTES), "COM0");
+        assert_eq!(windows.name("CONSOLE", MAX_NAME_BYTES), "CONSOLE");
+        assert_eq!(windows.name("Live.CON", MAX_NAME_BYTES), "Live.CON");
+        assert_eq!(PathProfile::Smb.name("CON", MAX_NAME_BYTES), "CON");
+        assert_eq!(PathProfile::Posix.name("CON", MAX_NAME_BYTES), "CON");
+    }
+
+    #[test]
+    fn trailing_dots_and_spaces_are_trimmed_on_windows() {
+        assert_eq!(
+            PathProfile::Windows.name("Vol. 2...", MAX_NAME_BYTES),
+            "Vol. 2"
+        );
+        assert_eq!(PathProfile::Smb.name("Live . ", MAX_NAME_BYTES), "Live");
+        assert_eq!(PathProfile::Windows.name("...", MAX_NAME_BYTES), "_");
+        assert_eq!(PathProfile::Windows.name("CON.", MAX_NAME_BYTES), "CON_");
+        assert_eq!(
+            PathProfile::Posix.name("Vol. 2...", MAX_NAME_BYTES),
+            "Vol. 2..."
+        );
+    }
+
+    #[test]
+    fn names_are_truncated_to_whole_characters() {
+        let posix = PathProfile::Posix;
+        assert_eq!(
+            posix.name(&"a".repeat(300), MAX_NAME_BYTES),
+            "a".repeat(255)
+        );
+        // each é takes two bytes, so an odd limit can't cut one in half
+        assert_eq!(posix.name(&"é".repeat(10), 5), "éé");
+        // truncating can leave a trailing space that has to go as well
+        assert_eq!(PathProfile::Windows.name("ab cd", 3), "ab");
+
+        let file_name = posix.file_name(&"a".repeat(300), "flac");
+        assert!(file_name.ends_with(".flac"));
+        assert_eq!(file_name.len() + PART_SUFFIX_BYTES, MAX_NAME_BYTES);
+    }
+
+    #[test]
+    fn file_paths_keep_the_extension_and_normalize_new_folders() {
+        let path = Path::new("/nonexistent-tdl/AC/DC?/Back.In.Black");
+        assert_eq!(
+            PathProfile::Windows.file_path(path, "flac"),
+            Path::new("/nonexistent-tdl/AC/DC_/Back.In.Black.flac")
+        );
+        // NFD input is stored as NFC
+        let path = Path::new("/nonexistent-tdl/Bjo\u{308}rk");
+        assert_eq!(
+            PathProfile::Posix.file_path(path, "m4a"),
+            Path::new("/nonexistent-tdl/Bj\u{f6}rk.m4a")
+        );
+        assert_eq!(
+            PathProfile::Ascii.file_path(path, "m4a"),
+            Path::new("/nonexistent-tdl/Bjork.m4a")
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use crate::api::models::SearchResult;
+use anyhow::{anyhow, Error};
+use console::Term;
+use dialoguer::{theme::ColorfulTheme, MultiSelect};
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Lets the user pick any number of search results and returns the URLs of the picks.
+///
+/// With a terminal the results are shown as a list navigated with the arrow keys, where
+/// space toggles an entry and enter confirms. Otherwise the results are printed with a
+/// number and the selection is read from a line of stdin, e.g. `1 3 4`.
+pub fn pick(results: Vec<SearchResult>) -> Result<Vec<String>, Error> {
+    if results.is_empty() {
+        return Ok(Vec::new());
+    }
+    let labels: Vec<String> = results.iter().map(label).collect();
+    let picked = match Term::stderr().is_term() && std::io::stdin().is_terminal() {
+        true => MultiSelect::with_theme(&ColorfulTheme::default())
+            .with_prompt("Select with space, confirm with enter")
+            .items(&labels)
+            .interact_on_opt(&Term::stderr())?
+            .unwrap_or_default(),
+        false => pick_numbers(&labels)?,
+    };
+    let mut results: Vec<Option<SearchResult>> = results.into_iter().map(Some).collect();
+    Ok(picked
+        .into_iter()
+        .filter_map(|i| results.get_mut(i).and_then(Option::take))
+        .map(|r| r.url)
+        .collect())
+}
+
+fn label(result: &SearchResult) -> String {
+    match result.artist.is_empty() {
+        true => format!("{} [{}]", result.name, result.id),
+        false => format!("{} - {} [{}]", result.name, result.artist, result.id),
+    }
+}
+
+fn pick_numbers(labels: &[String]) -> Result<Vec<usize>, Error> {
+    let mut stderr = std::io::stderr();
+    for (i, label) in labels.iter().enumerate() {
+        writeln!(stderr, "{:>3}) {label}", i + 1)?;
+    }
+    write!(stderr, "Select entries by number, separated by spaces: ")?;
+    stderr.flush()?;
+
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    line.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .map(|s| match s.parse::<usize>() {
+            Ok(n) if (1..=labels.len()).contains(&n) => Ok(n - 1),
+            _ => Err(anyhow!(
+                "Invalid selection '{s}', expected a number from 1 to {}",
+                labels.len()
+            )),
+        })
+        .collect()
+}
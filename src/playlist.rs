@@ -0,0 +1,301 @@
+use crate::normalize::PathProfile;
+use anyhow::Error;
+use log::debug;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use std::fmt::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[derive(SerializeDisplay, DeserializeFromStr, Clone, Debug, Copy, PartialEq, Eq)]
+/// Format of the playlist files written for a list, named after its file extension
+pub enum PlaylistFormat {
+    M3u8,
+    Xspf,
+    Pls,
+}
+
+impl fmt::Display for PlaylistFormat {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let str = match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Xspf => "xspf",
+            PlaylistFormat::Pls => "pls",
+        };
+        fmt.write_str(str)?;
+        Ok(())
+    }
+}
+
+impl FromStr for PlaylistFormat {
+    type Err = String;
+    fn from_str(input: &str) -> Result<PlaylistFormat, Self::Err> {
+        match input {
+            "m3u8" => Ok(PlaylistFormat::M3u8),
+            "xspf" => Ok(PlaylistFormat::Xspf),
+            "pls" => Ok(PlaylistFormat::Pls),
+            _ => Err("Error".to_string()),
+        }
+    }
+}
+
+/// Playlist files listing the tracks of a list in order, once all of them have finished.
+///
+/// Every track of the list holds a [`PlaylistSlot`], and a file per format is written when
+/// the last slot is dropped, into the deepest folder that contains all of the downloaded tracks.
+pub struct PlaylistFile {
+    name: String,
+    formats: Vec<PlaylistFormat>,
+    profile: PathProfile,
+    entries: Mutex<Entries>,
+}
+
+struct Entries {
+    entries: Vec<Option<PlaylistEntry>>,
+    remaining: usize,
+}
+
+pub struct PlaylistEntry {
+    pub path: PathBuf,
+    pub duration: usize,
+    pub artist: String,
+    pub title: String,
+}
+
+impl PlaylistFile {
+    /// Creates the files named `name` for a list of `len` tracks, returning a slot per track.
+    /// The file names are made safe for `profile` like the names of downloads.
+    pub fn slots(
+        name: String,
+        formats: Vec<PlaylistFormat>,
+        profile: PathProfile,
+        len: usize,
+    ) -> Vec<PlaylistSlot> {
+        let file = Arc::new(Self {
+            name,
+            formats,
+            profile,
+            entries: Mutex::new(Entries {
+                entries: (0..len).map(|_| None).collect(),
+                remaining: len,
+            }),
+        });
+        (0..len)
+            .map(|index| PlaylistSlot {
+                file: file.clone(),
+                index,
+                entry: None,
+            })
+            .collect()
+    }
+
+    fn finish(&self, index: usize, entry: Option<PlaylistEntry>) {
+        let mut entries = self.entries.lock().expect("Playlist lock poisoned");
+        entries.entries[index] = entry;
+        entries.remaining -= 1;
+        if entries.remaining > 0 {
+            return;
+        }
+        let entries: Vec<&PlaylistEntry> = entries.entries.iter().flatten().collect();
+        let dir = match common_dir(entries.iter().map(|e| e.path.as_path())) {
+            Some(dir) => dir,
+            None => return debug!("No tracks downloaded for playlist {}", self.name),
+        };
+        for format in &self.formats {
+            match self.write(&dir, &entries, *format) {
+                Ok(path) => debug!("Playlist written to {}", path.display()),
+                Err(e) => eprintln!("Unable to write {format} playlist {}: {e}", self.name),
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        dir: &Path,
+        entries: &[&PlaylistEntry],
+        format: PlaylistFormat,
+    ) -> Result<PathBuf, Error> {
+        // paths in the file are relative to the folder it is written to
+        let entries = entries
+            .iter()
+            .map(|e| Ok((e.path.strip_prefix(dir)?, *e)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let contents = match format {
+            PlaylistFormat::M3u8 => self.m3u8(&entries)?,
+            PlaylistFormat::Xspf => self.xspf(&entries)?,
+            PlaylistFormat::Pls => pls(&entries)?,
+        };
+        let path = self
+            .profile
+            .file_path(&dir.join(&self.name), &format.to_string());
+        std::fs::write(&path, contents)?;
+        Ok(path)
+    }
+
+    fn m3u8(&self, entries: &[(&Path, &PlaylistEntry)]) -> Result<String, Error> {
+        let mut m3u = String::from("#EXTM3U\n");
+        writeln!(m3u, "#PLAYLIST:{}", self.name)?;
+        for (path, entry) in entries {
+            writeln!(
+                m3u,
+                "#EXTINF:{},{} - {}",
+                entry.duration, entry.artist, entry.title
+            )?;
+            writeln!(m3u, "{}", path.display())?;
+        }
+        Ok(m3u)
+    }
+
+    fn xspf(&self, entries: &[(&Path, &PlaylistEntry)]) -> Result<String, Error> {
+        let mut xspf = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        writeln!(
+            xspf,
+            "<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">"
+        )?;
+        writeln!(xspf, "  <title>{}</title>", escape_xml(&self.name))?;
+        writeln!(xspf, "  <trackList>")?;
+        for (path, entry) in entries {
+            writeln!(xspf, "    <track>")?;
+            writeln!(
+                xspf,
+                "      <location>{}</location>",
+                escape_xml(&to_uri(path))
+            )?;
+            writeln!(
+                xspf,
+                "      <creator>{}</creator>",
+                escape_xml(&entry.artist)
+            )?;
+            writeln!(xspf, "      <title>{}</title>", escape_xml(&entry.title))?;
+            // XSPF durations are in milliseconds
+            writeln!(xspf, "      <duration>{}</duration>", entry.duration * 1000)?;
+            writeln!(xspf, "    </track>")?;
+        }
+        writeln!(xspf, "  </trackList>")?;
+        writeln!(xspf, "</playlist>")?;
+        Ok(xspf)
+    }
+}
+
+fn pls(entries: &[(&Path, &PlaylistEntry)]) -> Result<String, Error> {
+    let mut pls = String::from("[playlist]\n");
+    for (i, (path, entry)) in entries.iter().enumerate() {
+        let n = i + 1;
+        writeln!(pls, "File{n}={}", path.display())?;
+        writeln!(pls, "Title{n}={} - {}", entry.artist, entry.title)?;
+        writeln!(pls, "Length{n}={}", entry.duration)?;
+    }
+    writeln!(pls, "NumberOfEntries={}", entries.len())?;
+    writeln!(pls, "Version=2")?;
+    Ok(pls)
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// a relative URI of a relative path, percent-encoding everything but unreserved characters
+fn to_uri(path: &Path) -> String {
+    let segments: Vec<String> = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(s) => Some(s.to_string_lossy()),
+            _ => None,
+        })
+        .map(|s| {
+            s.bytes()
+                .map(|b| match b {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                        (b as char).to_string()
+                    }
+                    _ => format!("%{b:02X}"),
+                })
+                .collect()
+        })
+        .collect();
+    segments.join("/")
+}
+
+// the deepest folder shared by every path
+fn common_dir<'a>(mut paths: impl Iterator<Item = &'a Path>) -> Option<PathBuf> {
+    let mut dir = paths.next()?.parent()?.to_path_buf();
+    for path in paths {
+        while !path.starts_with(&dir) {
+            dir = dir.parent()?.to_path_buf();
+        }
+    }
+    Some(dir)
+}
+
+/// The place of a track in a [`PlaylistFile`], which is left out of the file unless
+/// [`PlaylistSlot::set`] is called before the slot is dropped.
+pub struct PlaylistSlot {
+    file: Arc<PlaylistFile>,
+    index: usize,
+    entry: Option<PlaylistEntry>,
+}
+
+impl PlaylistSlot {
+    /// Position of the track in the list, starting at 0
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn set(&mut self, entry: PlaylistEntry) {
+        self.entry = Some(entry);
+    }
+}
+
+impl Drop for PlaylistSlot {
+    fn drop(&mut self) {
+        self.file.finish(self.index, self.entry.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, profile: PathProfile) {
+        let path = dir.join("01 - Track.flac");
+        std::fs::write(&path, b"audio").unwrap();
+        for mut slot in
+            PlaylistFile::slots(name.to_string(), vec![PlaylistFormat::M3u8], profile, 1)
+        {
+            slot.set(PlaylistEntry {
+                path: path.clone(),
+                duration: 200,
+                artist: "Artist".to_string(),
+                title: "Track".to_string(),
+            });
+        }
+    }
+
+    #[test]
+    fn names_files_for_the_path_profile() {
+        let dir = std::env::temp_dir().join(format!("tdl-playlist-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        write(&dir, "CON", PathProfile::Windows);
+        write(&dir, "Best of.", PathProfile::Windows);
+        write(&dir, &"a".repeat(300), PathProfile::Posix);
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|n| n.ends_with(".m3u8"))
+            .collect();
+        names.sort();
+        let long = format!("{}.m3u8", "a".repeat(255 - ".part".len() - ".m3u8".len()));
+        assert_eq!(names, ["Best of.m3u8", "CON_.m3u8", long.as_str()]);
+
+        let m3u8 = std::fs::read_to_string(dir.join("CON_.m3u8")).unwrap();
+        assert!(m3u8.contains("01 - Track.flac"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+use crate::atoms::{find_atom, parse_atoms, read_u32, read_u64, Atom};
+use anyhow::{anyhow, Error};
+use std::path::Path;
+
+// size of the audio sample entry fields before its child atoms
+const AUDIO_SAMPLE_ENTRY_LEN: usize = 28;
+const STREAMINFO_LEN: usize = 34;
+
+/// Extracts the FLAC frames of a FLAC-in-MP4 file at `src` into a native FLAC file at `dst`.
+///
+/// The metadata blocks are taken from the `dfLa` atom of the FLAC sample entry, with the
+/// STREAMINFO sample count and frame sizes filled in from the container's sample tables.
+pub fn remux_flac(src: &Path, dst: &Path) -> Result<(), Error> {
+    let data = std::fs::read(src)?;
+    let top = parse_atoms(&data, 0..data.len())?;
+    let moov = top
+        .iter()
+        .find(|a| &a.kind == b"moov")
+        .ok_or_else(|| anyhow!("MP4 file has no moov atom"))?;
+    let stbl = find_atom(&data, moov.body(), &[b"trak", b"mdia", b"minf", b"stbl"])?
+        .ok_or_else(|| anyhow!("MP4 file has no sample table"))?;
+    let mut metadata = flac_metadata(&data, &stbl)?;
+
+    let frames = match top.iter().any(|a| &a.kind == b"moof") {
+        true => fragmented_frames(&data, &top, moov)?,
+        false => progressive_frames(&data, &top, &stbl)?,
+    };
+    patch_streaminfo(&mut metadata, &frames)?;
+
+    let mut out = Vec::with_capacity(metadata.len() + frames.data.len() + 4);
+    out.extend_from_slice(b"fLaC");
+    out.extend_from_slice(&metadata);
+    out.extend_from_slice(&frames.data);
+    std::fs::write(dst, out)?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Frames {
+    data: Vec<u8>,
+    samples: Option<u64>,
+    min_size: Option<usize>,
+    max_size: Option<usize>,
+}
+
+impl Frames {
+    fn push(&mut self, frame: &[u8]) {
+        self.data.extend_from_slice(frame);
+        self.min_size = Some(self.min_size.map_or(frame.len(), |m| m.min(frame.len())));
+        self.max_size = Some(self.max_size.map_or(frame.len(), |m| m.max(frame.len())));
+    }
+}
+
+// the FLAC metadata blocks stored in the dfLa atom of the fLaC sample entry
+fn flac_metadata(data: &[u8], stbl: &Atom) -> Result<Vec<u8>, Error> {
+    let stsd = find_atom(data, stbl.body(), &[b"stsd"])?
+        .ok_or_else(|| anyhow!("MP4 file has no sample description"))?;
+    // version, flags and entry count precede the sample entries
+    let entries = parse_atoms(data, stsd.body().start + 8..stsd.range.end)?;
+    let entry = entries
+        .iter()
+        .find(|a| &a.kind == b"fLaC")
+        .ok_or_else(|| anyhow!("MP4 file has no FLAC sample entry"))?;
+    let dfla = find_atom(
+        data,
+        entry.body().start + AUDIO_SAMPLE_ENTRY_LEN..entry.range.end,
+        &[b"dfLa"],
+    )?
+    .ok_or_else(|| anyhow!("FLAC sample entry has no dfLa atom"))?;
+    let metadata = data[dfla.body().start + 4..dfla.range.end].to_vec();
+    if metadata.len() < STREAMINFO_LEN + 4 || metadata[0] & 0x7f != 0 {
+        return Err(anyhow!("FLAC sample entry does not start with STREAMINFO"));
+    }
+    Ok(metadata)
+}
+
+fn fragmented_frames(data: &[u8], top: &[Atom], moov: &Atom) -> Result<Frames, Error> {
+    // defaults for fragments that don't set their own sample duration and size
+    let (mut trex_duration, mut trex_size) = (0, 0);
+    if let Some(trex) = find_atom(data, moov.body(), &[b"mvex", b"trex"])? {
+        let body = trex.body().start;
+        trex_duration = read_u32(data, body + 12)?;
+        trex_size = read_u32(data, body + 16)?;
+    }
+
+    let mut frames = Frames::default();
+    let mut samples = 0;
+    for moof in top.iter().filter(|a| &a.kind == b"moof") {
+        for traf in parse_atoms(data, moof.body())? {
+            if &traf.kind != b"traf" {
+                continue;
+            }
+            let children = parse_atoms(data, traf.body())?;
+            let tfhd = children
+                .iter()
+                .find(|a| &a.kind == b"tfhd")
+                .ok_or_else(|| anyhow!("Track fragment has no tfhd atom"))?;
+            let mut pos = tfhd.body().start;
+            let flags = read_u32(data, pos)? & 0xff_ffff;
+            // skip version, flags and track id
+            pos += 8;
+            let mut base = moof.range.start as u64;
+            if flags & 0x01 != 0 {
+                base = read_u64(data, pos)?;
+                pos += 8;
+            }
+            if flags & 0x02 != 0 {
+                pos += 4;
+            }
+            let mut default_duration = trex_duration;
+            if flags & 0x08 != 0 {
+                default_duration = read_u32(data, pos)?;
+                pos += 4;
+            }
+            let mut default_size = trex_size;
+            if flags & 0x10 != 0 {
+                default_size = read_u32(data, pos)?;
+            }
+
+            // runs without a data offset continue where the previous run ended
+            let mut data_pos = base;
+            for trun in children.iter().filter(|a| &a.kind == b"trun") {
+                let mut pos = trun.body().start;
+                let flags = read_u32(data, pos)? & 0xff_ffff;
+                let count = read_u32(data, pos + 4)?;
+                pos += 8;
+                if flags & 0x01 != 0 {
+                    let offset = read_u32(data, pos)? as i32;
+                    data_pos = base
+                        .checked_add_signed(offset.into())
+                        .ok_or_else(|| anyhow!("Invalid MP4 sample data offset"))?;
+                    pos += 4;
+                }
+                if flags & 0x04 != 0 {
+                    pos += 4;
+                }
+                for _ in 0..count {
+                    let mut duration = default_duration;
+                    if flags & 0x100 != 0 {
+                        duration = read_u32(data, pos)?;
+                        pos += 4;
+                    }
+                    let mut size = default_size;
+                    if flags & 0x200 != 0 {
+                        size = read_u32(data, pos)?;
+                        pos += 4;
+                    }
+                    if flags & 0x400 != 0 {
+                        pos += 4;
+                    }
+                    if flags & 0x800 != 0 {
+                        pos += 4;
+                    }
+                    let start = data_pos as usize;
+                    let frame = data
+                        .get(start..start + size as usize)
+                        .ok_or_else(|| anyhow!("MP4 sample extends past the end of the file"))?;
+                    frames.push(frame);
+                    data_pos += u64::from(size);
+                    samples += u64::from(duration);
+                }
+            }
+        }
+    }
+    frames.samples = Some(samples);
+    Ok(frames)
+}
+
+fn progressive_frames(data: &[u8], top: &[Atom], stbl: &Atom) -> Result<Frames, Error> {
+    // a single audio track, so its media data is the FLAC frames in order
+    let mut frames = Frames::default();
+    for mdat in top.iter().filter(|a| &a.kind == b"mdat") {
+        frames.data.extend_from_slice(&data[mdat.body()]);
+    }
+    // the audio track's time scale is its sample rate, so the durations are sample counts
+    if let Some(stts) = find_atom(data, stbl.body(), &[b"stts"])? {
+        let body = stts.body().start;
+        let entries = read_u32(data, body + 4)? as usize;
+        let mut samples = 0;
+        for i in 0..entries {
+            let count = read_u32(data, body + 8 + i * 8)?;
+            let delta = read_u32(data, body + 12 + i * 8)?;
+            samples += u64::from(count) * u64::from(delta);
+        }
+        frames.samples = Some(samples);
+    }
+    Ok(frames)
+}
+
+fn patch_streaminfo(metadata: &mut [u8], frames: &Frames) -> Result<(), Error> {
+    let info = &mut metadata[4..4 + STREAMINFO_LEN];
+    if let Some(min) = frames.min_size {
+        info[4..7].copy_from_slice(&(min as u32).to_be_bytes()[1..]);
+    }
+    if let Some(max) = frames.max_size {
+        info[7..10].copy_from_slice(&(max as u32).to_be_bytes()[1..]);
+    }
+    // the total sample count is the low 36 bits after sample rate, channels and bit depth
+    if let Some(samples) = frames.samples.filter(|s| *s > 0) {
+        let mut packed = u64::from_be_bytes(info[10..18].try_into()?);
+        packed = (packed & !0xf_ffff_ffff) | (samples & 0xf_ffff_ffff);
+        info[10..18].copy_from_slice(&packed.to_be_bytes());
+    }
+
+    // only the final metadata block may carry the last block flag
+    let mut pos = 0;
+    while pos + 4 <= metadata.len() {
+        let len = u32::from_be_bytes([0, metadata[pos + 1], metadata[pos + 2], metadata[pos + 3]]);
+        let next = pos + 4 + len as usize;
+        match next >= metadata.len() {
+            true => metadata[pos] |= 0x80,
+            false => metadata[pos] &= 0x7f,
+        }
+        pos = next;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut atom = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend_from_slice(kind);
+        atom.extend_from_slice(body);
+        atom
+    }
+
+    // STREAMINFO of 44.1 kHz 16 bit stereo with 4096 sample blocks, frame sizes and sample
+    // count as given
+    fn streaminfo(last: bool, min: u32, max: u32, samples: u64) -> Vec<u8> {
+        let mut block = vec![if last { 0x80 } else { 0 }, 0, 0, STREAMINFO_LEN as u8];
+        block.extend_from_slice(&4096u16.to_be_bytes());
+        block.extend_from_slice(&4096u16.to_be_bytes());
+        block.extend_from_slice(&min.to_be_bytes()[1..]);
+        block.extend_from_slice(&max.to_be_bytes()[1..]);
+        let packed = (44_100u64 << 44) | (1 << 41) | (15 << 36) | samples;
+        block.extend_from_slice(&packed.to_be_bytes());
+        block.extend_from_slice(&[0xab; 16]);
+        block
+    }
+
+    const PADDING: [u8; 8] = [0x01, 0, 0, 4, 0, 0, 0, 0];
+
+    // a moov atom with a FLAC sample entry, whose STREAMINFO wrongly claims to be the last
+    // block before a padding block
+    fn moov(stts: &[u8], mvex: &[u8]) -> Vec<u8> {
+        let mut metadata = streaminfo(true, 0, 0, 0);
+        metadata.extend_from_slice(&PADDING);
+        let dfla = atom(b"dfLa", &[&[0u8; 4][..], &metadata].concat());
+        let entry = atom(
+            b"fLaC",
+            &[&[0u8; AUDIO_SAMPLE_ENTRY_LEN][..], &dfla].concat(),
+        );
+        let stsd = atom(b"stsd", &[&[0, 0, 0, 0, 0, 0, 0, 1][..], &entry].concat());
+        let stbl = atom(b"stbl", &[stsd, stts.to_vec()].concat());
+        let minf = atom(b"minf", &stbl);
+        let trak = atom(b"trak", &atom(b"mdia", &minf));
+        atom(b"moov", &[trak, mvex.to_vec()].concat())
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    // a moof and its mdat, with the samples' sizes and optionally their durations in the trun
+    fn fragment(frames: &[&[u8]], durations: Option<&[u32]>) -> Vec<u8> {
+        let flags = match durations {
+            Some(_) => 0x301,
+            None => 0x201,
+        };
+        let mut samples = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            if let Some(durations) = durations {
+                samples.push(durations[i]);
+            }
+            samples.push(frame.len() as u32);
+        }
+        let build = |offset: u32| {
+            let tfhd = atom(b"tfhd", &u32s(&[0, 1]));
+            let trun = atom(
+                b"trun",
+                &[u32s(&[flags, frames.len() as u32, offset]), u32s(&samples)].concat(),
+            );
+            let traf = atom(b"traf", &[tfhd, trun].concat());
+            atom(b"moof", &[atom(b"mfhd", &u32s(&[0, 1])), traf].concat())
+        };
+        // the samples start right after the moof and the mdat header
+        let moof = build(0);
+        let moof = build(moof.len() as u32 + 8);
+        [moof, atom(b"mdat", &frames.concat())].concat()
+    }
+
+    fn remux(name: &str, mp4: &[u8]) -> Vec<u8> {
+        let dir = std::env::temp_dir();
+        let src = dir.join(format!("tdl-remux-{}-{name}.mp4", std::process::id()));
+        let dst = src.with_extension("flac");
+        std::fs::write(&src, mp4).unwrap();
+        remux_flac(&src, &dst).unwrap();
+        let flac = std::fs::read(&dst).unwrap();
+        std::fs::remove_file(&src).unwrap();
+        std::fs::remove_file(&dst).unwrap();
+        flac
+    }
+
+    fn expected(streaminfo: Vec<u8>, frames: &[&[u8]]) -> Vec<u8> {
+        let mut padding = PADDING;
+        padding[0] |= 0x80;
+        [
+            b"fLaC".to_vec(),
+            streaminfo,
+            padding.to_vec(),
+            frames.concat(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn remuxes_progressive_mp4() {
+        let frames: [&[u8]; 2] = [&[0xff, 0xf8, 1, 2, 3], &[0xff, 0xf8, 4, 5, 6, 7]];
+        // two samples of 4096 and one of 1000
+        let stts = atom(b"stts", &u32s(&[0, 2, 2, 4096, 1, 1000]));
+        let mp4 = [
+            atom(b"ftyp", b"isom"),
+            moov(&stts, &[]),
+            atom(b"mdat", &frames.concat()),
+        ]
+        .concat();
+
+        // the frame sizes aren't known without parsing the frames, so they stay unset
+        assert_eq!(
+            remux("progressive", &mp4),
+            expected(streaminfo(false, 0, 0, 9192), &frames)
+        );
+    }
+
+    #[test]
+    fn remuxes_fragmented_mp4() {
+        let frames: [&[u8]; 3] = [&[1; 10], &[2; 14], &[3; 6]];
+        // 4096 samples per frame unless the fragment says otherwise
+        let trex = atom(b"trex", &u32s(&[0, 1, 1, 4096, 0, 0]));
+        let mp4 = [
+            atom(b"ftyp", b"iso6"),
+            moov(&[], &atom(b"mvex", &trex)),
+            fragment(&frames[..2], None),
+            fragment(&frames[2..], Some(&[1000])),
+        ]
+        .concat();
+
+        assert_eq!(
+            remux("fragmented", &mp4),
+            expected(streaminfo(false, 6, 14, 9192), &frames)
+        );
+    }
+
+    #[test]
+    fn rejects_mp4_without_flac() {
+        let dir = std::env::temp_dir();
+        let src = dir.join(format!("tdl-remux-{}-aac.mp4", std::process::id()));
+        let stsd = atom(
+            b"stsd",
+            &[&[0, 0, 0, 0, 0, 0, 0, 1][..], &atom(b"mp4a", &[0; 28])].concat(),
+        );
+        let stbl = atom(b"stbl", &stsd);
+        let trak = atom(b"trak", &atom(b"mdia", &atom(b"minf", &stbl)));
+        std::fs::write(&src, atom(b"moov", &trak)).unwrap();
+        let err = remux_flac(&src, &src.with_extension("flac")).unwrap_err();
+        assert_eq!(err.to_string(), "MP4 file has no FLAC sample entry");
+        std::fs::remove_file(&src).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+use crate::api::models::{ListItem, Track};
+use crate::api::TidalClient;
+use crate::download::move_file;
+use crate::models::{Action, ActionKind, TrackFile};
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// The tracks of a synced source that are on disk, saved between runs of `sync` in a
+/// state file per source.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SyncState {
+    pub source: String,
+    pub tracks: BTreeMap<usize, SyncedTrack>,
+    #[serde(skip)]
+    file: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncedTrack {
+    pub title: String,
+    pub path: PathBuf,
+    /// whether sync wrote the file, as only those files are deleted or moved by sync
+    #[serde(default)]
+    pub created: bool,
+}
+
+/// The changes of a source since it was last synced
+pub struct SyncDiff {
+    /// tracks that are new to the source, or whose file was deleted since
+    pub added: Vec<Track>,
+    /// tracks that are no longer in the source
+    pub removed: Vec<(usize, SyncedTrack)>,
+    /// the files of tracks that are still in the source and on disk
+    pub kept: HashMap<usize, PathBuf>,
+}
+
+/// What to do with the files of tracks that are no longer in the source
+pub enum Removal {
+    Keep,
+    Delete,
+    MoveTo(PathBuf),
+}
+
+impl SyncState {
+    /// Reads the state of `action` from `dir`, or starts an empty one if it was never synced.
+    pub fn load(dir: &str, action: &Action, source: &str) -> Result<Self, Error> {
+        let file = Path::new(dir).join(format!("{}-{}.json", action.kind, action.id));
+        match std::fs::read_to_string(&file) {
+            Ok(contents) => Ok(Self {
+                file,
+                ..serde_json::from_str(&contents)?
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self {
+                source: source.to_string(),
+                tracks: BTreeMap::new(),
+                file,
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(dir) = self.file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.file, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn diff(&self, tracks: Vec<Track>) -> SyncDiff {
+        let ids: HashSet<usize> = tracks.iter().map(|t| t.id).collect();
+        let mut added = Vec::new();
+        let mut kept = HashMap::new();
+        for track in tracks {
+            match self.tracks.get(&track.id) {
+                Some(synced) if synced.path.exists() => {
+                    kept.insert(track.id, synced.path.clone());
+                }
+                _ => added.push(track),
+            }
+        }
+        let removed = self
+            .tracks
+            .iter()
+            .filter(|(id, _)| !ids.contains(id))
+            .map(|(id, synced)| (*id, synced.clone()))
+            .collect();
+        SyncDiff {
+            added,
+            removed,
+            kept,
+        }
+    }
+
+    /// Records the files of the added tracks that made it to disk, returning the tracks that
+    /// didn't. Files that were already on disk are recorded as not created by sync.
+    pub fn record(&mut self, added: Vec<Track>, files: &HashMap<usize, TrackFile>) -> Vec<Track> {
+        let mut failed = Vec::new();
+        for track in added {
+            match files.get(&track.id) {
+                Some(file) => {
+                    let synced = SyncedTrack {
+                        title: format!("{} - {}", track.artist.name, track.title),
+                        path: file.path.clone(),
+                        created: file.created,
+                    };
+                    self.tracks.insert(track.id, synced);
+                }
+                None => failed.push(track),
+            }
+        }
+        failed
+    }
+
+    /// Applies `removal` to the file of a track that left the source and forgets the track,
+    /// so it is only reported as removed once. Files sync didn't create are always left in
+    /// place. Returns where the file went.
+    pub async fn remove(&mut self, id: usize, removal: &Removal) -> Result<String, Error> {
+        let synced = self
+            .tracks
+            .get(&id)
+            .ok_or_else(|| anyhow!("Track {id} is not synced"))?;
+        let outcome = match removal {
+            Removal::Keep => "kept".to_string(),
+            _ if !synced.created => "kept, not created by sync".to_string(),
+            Removal::Delete => match std::fs::remove_file(&synced.path) {
+                Ok(_) => "deleted".to_string(),
+                Err(e) if e.kind() == ErrorKind::NotFound => "already gone".to_string(),
+                Err(e) => return Err(e.into()),
+            },
+            Removal::MoveTo(dir) => {
+                std::fs::create_dir_all(dir)?;
+                let file_name = synced
+                    .path
+                    .file_name()
+                    .ok_or_else(|| anyhow!("Synced path has no file name"))?;
+                let dest = dir.join(file_name);
+                move_file(&synced.path, &dest).await?;
+                format!("moved to {}", dest.display())
+            }
+        };
+        self.tracks.remove(&id);
+        Ok(outcome)
+    }
+}
+
+/// Lists the tracks of a source that can be synced, which are playlists, albums, mixes and
+/// the favorite tracks of the logged in user.
+pub async fn get_source_tracks(client: &TidalClient, action: &Action) -> Result<Vec<Track>, Error> {
+    let media = &client.media;
+    let items = match action.kind {
+        ActionKind::Album | ActionKind::Playlist => {
+            let url = format!(
+                "https://api.tidal.com/v1/{}s/{}/items",
+                action.kind, action.id
+            );
+            media.get_items::<ListItem>(&url, None, None).await?
+        }
+        ActionKind::Mix => media.get_mix_items(&action.id).await?,
+        ActionKind::Favorites if action.id == "tracks" => {
+            return media.get_favorites::<Track>("tracks").await
+        }
+        _ => {
+            return Err(anyhow!(
+                "Only playlists, albums, mixes and favorites:tracks can be synced"
+            ))
+        }
+    };
+    Ok(items
+        .into_iter()
+        .filter_map(|item| match item {
+            ListItem::Track(track) => Some(track),
+            ListItem::Video(_) => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::str::FromStr;
+
+    fn track(id: usize) -> Track {
+        serde_json::from_value(json!({
+            "id": id,
+            "title": format!("Track {id}"),
+            "duration": 200,
+            "trackNumber": 1,
+            "volumeNumber": 1,
+            "isrc": "",
+            "explicit": false,
+            "audioQuality": "LOSSLESS",
+            "copyright": "",
+            "artist": { "id": 1, "name": "Artist" },
+            "artists": [],
+            "album": { "id": 1 },
+            "allowStreaming": true,
+            "mixes": {},
+        }))
+        .unwrap()
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tdl-sync-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn state(dir: &Path) -> SyncState {
+        let action = Action::from_str("tidal://album/1").unwrap();
+        SyncState::load(dir.to_str().unwrap(), &action, "tidal://album/1").unwrap()
+    }
+
+    fn file(path: &Path, created: bool) -> TrackFile {
+        std::fs::write(path, b"audio").unwrap();
+        TrackFile {
+            path: path.to_path_buf(),
+            created,
+        }
+    }
+
+    #[test]
+    fn diffs_against_the_files_on_disk() {
+        let dir = test_dir("diff");
+        let mut state = state(&dir);
+        let files = HashMap::from([
+            (1, file(&dir.join("1.flac"), true)),
+            (2, file(&dir.join("2.flac"), true)),
+            (3, file(&dir.join("3.flac"), true)),
+        ]);
+        state.record(vec![track(1), track(2), track(3)], &files);
+        std::fs::remove_file(dir.join("2.flac")).unwrap();
+
+        let diff = state.diff(vec![track(1), track(2), track(4)]);
+        let added: Vec<usize> = diff.added.iter().map(|t| t.id).collect();
+        assert_eq!(added, [2, 4]);
+        assert_eq!(diff.kept, HashMap::from([(1, dir.join("1.flac"))]));
+        let removed: Vec<usize> = diff.removed.iter().map(|(id, _)| *id).collect();
+        assert_eq!(removed, [3]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_failed_and_existing_files() {
+        let dir = test_dir("record");
+        let mut state = state(&dir);
+        let files = HashMap::from([
+            (1, file(&dir.join("1.flac"), true)),
+            (2, file(&dir.join("2.flac"), false)),
+        ]);
+        let failed = state.record(vec![track(1), track(2), track(3)], &files);
+
+        assert_eq!(failed.iter().map(|t| t.id).collect::<Vec<_>>(), [3]);
+        assert!(state.tracks[&1].created);
+        assert!(!state.tracks[&2].created);
+        assert_eq!(state.tracks[&1].title, "Artist - Track 1");
+        assert!(!state.tracks.contains_key(&3));
+
+        state.save().unwrap();
+        let loaded = self::state(&dir);
+        assert_eq!(loaded.tracks.len(), 2);
+        assert!(loaded.tracks[&1].created && !loaded.tracks[&2].created);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_removes_files_sync_created() {
+        let dir = test_dir("remove");
+        let moved = dir.join("removed");
+        let mut state = state(&dir);
+        let files = HashMap::from([
+            (1, file(&dir.join("1.flac"), true)),
+            (2, file(&dir.join("2.flac"), false)),
+            (3, file(&dir.join("3.flac"), true)),
+            (4, file(&dir.join("4.flac"), false)),
+            (5, file(&dir.join("5.flac"), true)),
+        ]);
+        let tracks = (1..=5).map(track).collect();
+        state.record(tracks, &files);
+
+        assert_eq!(state.remove(1, &Removal::Delete).await.unwrap(), "deleted");
+        assert!(!dir.join("1.flac").exists());
+        let outcome = state.remove(2, &Removal::Delete).await.unwrap();
+        assert_eq!(outcome, "kept, not created by sync");
+        assert!(dir.join("2.flac").exists());
+
+        let outcome = state.remove(3, &Removal::MoveTo(moved.clone())).await;
+        assert_eq!(
+            outcome.unwrap(),
+            format!("moved to {}", moved.join("3.flac").display())
+        );
+        assert!(!dir.join("3.flac").exists() && moved.join("3.flac").exists());
+        state
+            .remove(4, &Removal::MoveTo(moved.clone()))
+            .await
+            .unwrap();
+        assert!(dir.join("4.flac").exists() && !moved.join("4.flac").exists());
+
+        assert_eq!(state.remove(5, &Removal::Keep).await.unwrap(), "kept");
+        assert!(dir.join("5.flac").exists());
+        // every removed track is forgotten, so it is only reported once
+        assert!(state.tracks.is_empty());
+        assert!(state.remove(5, &Removal::Keep).await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,46 @@
+use super::TrackTags;
+use anyhow::Error;
+use metaflac::block::PictureType::CoverFront;
+use metaflac::Tag;
+use std::path::Path;
+
+pub fn write_tags(path: &Path, tags: &TrackTags) -> Result<(), Error> {
+    let mut tag = Tag::read_from_path(path)?;
+    let explicit = match tags.explicit {
+        true => "1",
+        false => "0",
+    };
+    tag.set_vorbis("TITLE", vec![tags.title.clone()]);
+    tag.set_vorbis("ARTIST", tags.artists.clone());
+    tag.set_vorbis("ALBUMARTIST", vec![tags.album_artist.clone()]);
+    tag.set_vorbis("ALBUM", vec![tags.album.clone()]);
+    tag.set_vorbis("TRACKNUMBER", vec![tags.track_number.to_string()]);
+    tag.set_vorbis("DISCNUMBER", vec![tags.disc_number.to_string()]);
+    tag.set_vorbis("COPYRIGHT", vec![tags.copyright.clone()]);
+    tag.set_vorbis("ISRC", vec![tags.isrc.clone()]);
+    tag.set_vorbis("EXPLICIT", vec![explicit]);
+    tag.set_vorbis("ITUNESADVISORY", vec![explicit]);
+    tag.set_vorbis("TIDAL_TRACK_ID", vec![tags.track_id.to_string()]);
+    tag.set_vorbis("TIDAL_ALBUM_ID", vec![tags.album_id.to_string()]);
+    // optional values are removed rather than left over from a previous tagging
+    let optional = [
+        ("DATE", tags.date.clone()),
+        ("TOTALDISCS", tags.total_discs.map(|v| v.to_string())),
+        ("TOTALTRACKS", tags.total_tracks.map(|v| v.to_string())),
+        ("VERSION", tags.version.clone()),
+    ];
+    for (key, value) in optional {
+        match value {
+            Some(value) => tag.set_vorbis(key, vec![value]),
+            None => tag.remove_vorbis(key),
+        }
+    }
+
+    tag.remove_picture_type(CoverFront);
+    if let Some(cover) = &tags.cover {
+        tag.add_picture(cover.content_type.clone(), CoverFront, cover.data.clone());
+    }
+
+    tag.save()?;
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use crate::api::models::Cover;
+use anyhow::{anyhow, Error};
+use std::path::Path;
+
+mod flac;
+mod mp4;
+
+/// Metadata of a downloaded track, written in the tag format of its container.
+pub struct TrackTags {
+    pub title: String,
+    pub version: Option<String>,
+    pub artists: Vec<String>,
+    pub album_artist: String,
+    pub album: String,
+    pub date: Option<String>,
+    pub track_number: usize,
+    pub total_tracks: Option<i64>,
+    pub disc_number: usize,
+    pub total_discs: Option<i64>,
+    pub copyright: String,
+    pub isrc: String,
+    pub explicit: bool,
+    pub track_id: usize,
+    pub album_id: usize,
+    pub cover: Option<Cover>,
+}
+
+/// Replaces the tags of the file at `path`, dispatching on the file extension
+/// of the finished download since `path` itself may be a `.part` file.
+pub fn write_tags(path: &Path, extension: &str, tags: &TrackTags) -> Result<(), Error> {
+    match extension {
+        "flac" => flac::write_tags(path, tags),
+        "m4a" => mp4::write_tags(path, tags),
+        _ => Err(anyhow!("Unable to write tags to .{extension} files")),
+    }
+}
@@ -0,0 +1,367 @@
+use super::TrackTags;
+use crate::atoms::{parse_atoms, read_u32, read_u64, Atom};
+use anyhow::{anyhow, Error};
+use std::ops::Range;
+use std::path::Path;
+
+// data atom type indicators
+const TYPE_BINARY: u32 = 0;
+const TYPE_UTF8: u32 = 1;
+const TYPE_JPEG: u32 = 13;
+const TYPE_PNG: u32 = 14;
+const TYPE_INTEGER: u32 = 21;
+
+// atoms that only contain other atoms, and lead to the chunk offset tables
+const CONTAINERS: [&[u8; 4]; 5] = [b"moov", b"trak", b"mdia", b"minf", b"stbl"];
+
+/// Replaces the iTunes style `moov/udta/meta/ilst` atoms of an MP4 file.
+pub fn write_tags(path: &Path, tags: &TrackTags) -> Result<(), Error> {
+    let data = std::fs::read(path)?;
+    let top = parse_atoms(&data, 0..data.len())?;
+    let moov = top
+        .iter()
+        .find(|a| &a.kind == b"moov")
+        .ok_or_else(|| anyhow!("MP4 file has no moov atom"))?;
+
+    // keep everything in moov except the previous user data, which is rebuilt
+    let mut children = Vec::new();
+    let mut udta_children = Vec::new();
+    for atom in parse_atoms(&data, moov.body())? {
+        if &atom.kind != b"udta" {
+            children.extend_from_slice(&data[atom.range.clone()]);
+            continue;
+        }
+        for child in parse_atoms(&data, atom.body())? {
+            if &child.kind != b"meta" {
+                udta_children.extend_from_slice(&data[child.range.clone()]);
+            }
+        }
+    }
+    udta_children.extend(meta_atom(tags));
+    children.extend(atom(b"udta", &udta_children));
+    let mut new_moov = atom(b"moov", &children);
+
+    // sample data stored after moov moves by the change in size of moov
+    let delta = new_moov.len() as i64 - moov.range.len() as i64;
+    let mdat_after_moov = top
+        .iter()
+        .any(|a| &a.kind == b"mdat" && a.range.start > moov.range.start);
+    if delta != 0 && mdat_after_moov {
+        shift_chunk_offsets(&mut new_moov, delta)?;
+    }
+
+    let mut out = Vec::with_capacity(data.len() + new_moov.len());
+    out.extend_from_slice(&data[..moov.range.start]);
+    out.extend_from_slice(&new_moov);
+    out.extend_from_slice(&data[moov.range.end..]);
+    std::fs::write(path, out)?;
+    Ok(())
+}
+
+fn shift_chunk_offsets(moov: &mut [u8], delta: i64) -> Result<(), Error> {
+    let body = parse_atoms(moov, 0..moov.len())?
+        .first()
+        .map(Atom::body)
+        .ok_or_else(|| anyhow!("MP4 file has no moov atom"))?;
+    shift_in(moov, body, delta)
+}
+
+fn shift_in(data: &mut [u8], range: Range<usize>, delta: i64) -> Result<(), Error> {
+    for atom in parse_atoms(data, range)? {
+        let body = atom.body();
+        match &atom.kind {
+            kind if CONTAINERS.contains(&kind) => shift_in(data, body, delta)?,
+            // full box header, entry count, then 32 or 64 bit offsets
+            b"stco" | b"co64" => {
+                let width = if &atom.kind == b"stco" { 4 } else { 8 };
+                let count = read_u32(data, body.start + 4)? as usize;
+                let entries = body.start + 8;
+                if entries + count * width > body.end {
+                    return Err(anyhow!("Invalid MP4 chunk offset table"));
+                }
+                for i in 0..count {
+                    let pos = entries + i * width;
+                    if width == 4 {
+                        let offset = u32::try_from(read_u32(data, pos)? as i64 + delta)?;
+                        data[pos..pos + 4].copy_from_slice(&offset.to_be_bytes());
+                    } else {
+                        let offset = u64::try_from(read_u64(data, pos)? as i64 + delta)?;
+                        data[pos..pos + 8].copy_from_slice(&offset.to_be_bytes());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut atom = Vec::with_capacity(body.len() + 8);
+    atom.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    atom.extend_from_slice(kind);
+    atom.extend_from_slice(body);
+    atom
+}
+
+// an atom with a version and flags field before its body
+fn full_atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut full = vec![0; 4];
+    full.extend_from_slice(body);
+    atom(kind, &full)
+}
+
+fn data_atom(type_indicator: u32, value: &[u8]) -> Vec<u8> {
+    let mut body = type_indicator.to_be_bytes().to_vec();
+    // locale
+    body.extend_from_slice(&[0; 4]);
+    body.extend_from_slice(value);
+    atom(b"data", &body)
+}
+
+fn text_item(kind: &[u8; 4], value: &str) -> Vec<u8> {
+    atom(kind, &data_atom(TYPE_UTF8, value.as_bytes()))
+}
+
+// track and disc numbers are stored as a pair of number and total
+fn pair_item(kind: &[u8; 4], number: usize, total: Option<i64>) -> Vec<u8> {
+    let mut value = vec![0; 2];
+    value.extend_from_slice(&(number as u16).to_be_bytes());
+    value.extend_from_slice(&(total.unwrap_or(0) as u16).to_be_bytes());
+    if kind == b"trkn" {
+        value.extend_from_slice(&[0; 2]);
+    }
+    atom(kind, &data_atom(TYPE_BINARY, &value))
+}
+
+// values without a dedicated atom are stored as "----" atoms in the iTunes namespace
+fn freeform_item(name: &str, value: &str) -> Vec<u8> {
+    let mut body = full_atom(b"mean", b"com.apple.iTunes");
+    body.extend(full_atom(b"name", name.as_bytes()));
+    body.extend(data_atom(TYPE_UTF8, value.as_bytes()));
+    atom(b"----", &body)
+}
+
+fn meta_atom(tags: &TrackTags) -> Vec<u8> {
+    let title = match &tags.version {
+        Some(version) => format!("{} ({version})", tags.title),
+        None => tags.title.clone(),
+    };
+    let mut items = Vec::new();
+    items.extend(text_item(b"\xa9nam", &title));
+    items.extend(text_item(b"\xa9ART", &tags.artists.join(", ")));
+    items.extend(text_item(b"aART", &tags.album_artist));
+    items.extend(text_item(b"\xa9alb", &tags.album));
+    if let Some(date) = &tags.date {
+        items.extend(text_item(b"\xa9day", date));
+    }
+    items.extend(pair_item(b"trkn", tags.track_number, tags.total_tracks));
+    items.extend(pair_item(b"disk", tags.disc_number, tags.total_discs));
+    items.extend(text_item(b"cprt", &tags.copyright));
+    // 1 marks explicit content, 0 no advisory
+    items.extend(atom(
+        b"rtng",
+        &data_atom(TYPE_INTEGER, &[u8::from(tags.explicit)]),
+    ));
+    if let Some(cover) = &tags.cover {
+        let type_indicator = match cover.content_type.as_str() {
+            "image/png" => TYPE_PNG,
+            _ => TYPE_JPEG,
+        };
+        items.extend(atom(b"covr", &data_atom(type_indicator, &cover.data)));
+    }
+    items.extend(freeform_item("ISRC", &tags.isrc));
+    items.extend(freeform_item("TIDAL_TRACK_ID", &tags.track_id.to_string()));
+    items.extend(freeform_item("TIDAL_ALBUM_ID", &tags.album_id.to_string()));
+
+    // pre_defined, handler type, reserved fields and an empty name
+    let mut hdlr = vec![0; 4];
+    hdlr.extend_from_slice(b"mdirappl");
+    hdlr.extend_from_slice(&[0; 9]);
+    let mut meta = full_atom(b"hdlr", &hdlr);
+    meta.extend(atom(b"ilst", &items));
+    full_atom(b"meta", &meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atoms::find_atom;
+
+    const SAMPLES: &[u8] = b"first chunk|second chunk";
+
+    fn tags() -> TrackTags {
+        TrackTags {
+            title: "Title".to_string(),
+            version: Some("Live".to_string()),
+            artists: vec!["One".to_string(), "Two".to_string()],
+            album_artist: "One".to_string(),
+            album: "Album".to_string(),
+            date: Some("2020-01-02".to_string()),
+            track_number: 3,
+            total_tracks: Some(12),
+            disc_number: 1,
+            total_discs: Some(2),
+            copyright: "(C) Label".to_string(),
+            isrc: "USABC2000001".to_string(),
+            explicit: true,
+            track_id: 123,
+            album_id: 456,
+            cover: None,
+        }
+    }
+
+    fn chunk_offsets(kind: &[u8; 4], offsets: &[u64]) -> Vec<u8> {
+        let mut body = (offsets.len() as u32).to_be_bytes().to_vec();
+        for offset in offsets {
+            match kind {
+                b"stco" => body.extend_from_slice(&(*offset as u32).to_be_bytes()),
+                _ => body.extend_from_slice(&offset.to_be_bytes()),
+            }
+        }
+        full_atom(kind, &body)
+    }
+
+    fn trak(offsets: Vec<u8>) -> Vec<u8> {
+        atom(
+            b"trak",
+            &atom(b"mdia", &atom(b"minf", &atom(b"stbl", &offsets))),
+        )
+    }
+
+    // an MP4 with an stco and a co64 track pointing at the two chunks of SAMPLES, and
+    // previous user data with tags to replace and a name to keep
+    fn mp4(mdat_first: bool) -> Vec<u8> {
+        let ftyp = atom(b"ftyp", b"M4A ");
+        let mdat = atom(b"mdat", SAMPLES);
+        let old_meta = full_atom(b"meta", &atom(b"ilst", &text_item(b"\xa9nam", "Old")));
+        let udta = atom(b"udta", &[atom(b"name", b"kept"), old_meta].concat());
+        let moov = |offsets: &[u64]| {
+            let traks = [
+                trak(chunk_offsets(b"stco", offsets)),
+                trak(chunk_offsets(b"co64", offsets)),
+            ];
+            atom(b"moov", &[traks.concat(), udta.clone()].concat())
+        };
+        let moov_len = moov(&[0, 0]).len();
+        let mdat_start = match mdat_first {
+            true => ftyp.len(),
+            false => ftyp.len() + moov_len,
+        } as u64;
+        let moov = moov(&[mdat_start + 8, mdat_start + 8 + 12]);
+        match mdat_first {
+            true => [ftyp, mdat, moov].concat(),
+            false => [ftyp, moov, mdat].concat(),
+        }
+    }
+
+    fn write(name: &str, mp4: &[u8]) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("tdl-tags-{}-{name}.m4a", std::process::id()));
+        std::fs::write(&path, mp4).unwrap();
+        write_tags(&path, &tags()).unwrap();
+        let written = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        written
+    }
+
+    fn moov(data: &[u8]) -> Atom {
+        parse_atoms(data, 0..data.len())
+            .unwrap()
+            .into_iter()
+            .find(|a| &a.kind == b"moov")
+            .unwrap()
+    }
+
+    fn read_offsets(data: &[u8], trak: usize) -> Vec<u64> {
+        let traks: Vec<Atom> = parse_atoms(data, moov(data).body())
+            .unwrap()
+            .into_iter()
+            .filter(|a| &a.kind == b"trak")
+            .collect();
+        let stbl = find_atom(data, traks[trak].body(), &[b"mdia", b"minf", b"stbl"])
+            .unwrap()
+            .unwrap();
+        let table = &parse_atoms(data, stbl.body()).unwrap()[0];
+        let count = read_u32(data, table.body().start + 4).unwrap() as usize;
+        (0..count)
+            .map(|i| match &table.kind {
+                b"stco" => read_u32(data, table.body().start + 8 + i * 4).unwrap() as u64,
+                _ => read_u64(data, table.body().start + 8 + i * 8).unwrap(),
+            })
+            .collect()
+    }
+
+    // the value of a data atom in an ilst item, or of the freeform item with `name`
+    fn item(data: &[u8], kind: &[u8; 4], name: Option<&str>) -> Vec<u8> {
+        let meta = find_atom(data, moov(data).body(), &[b"udta", b"meta"])
+            .unwrap()
+            .unwrap();
+        let ilst = find_atom(data, meta.body().start + 4..meta.range.end, &[b"ilst"])
+            .unwrap()
+            .unwrap();
+        for item in parse_atoms(data, ilst.body()).unwrap() {
+            if &item.kind != kind {
+                continue;
+            }
+            let children = parse_atoms(data, item.body()).unwrap();
+            if let Some(name) = name {
+                let atom = children.iter().find(|a| &a.kind == b"name").unwrap();
+                if &data[atom.body().start + 4..atom.range.end] != name.as_bytes() {
+                    continue;
+                }
+            }
+            let value = children.iter().find(|a| &a.kind == b"data").unwrap();
+            return data[value.body().start + 8..value.range.end].to_vec();
+        }
+        panic!("No {} item", String::from_utf8_lossy(kind));
+    }
+
+    #[test]
+    fn writes_ilst_and_shifts_chunk_offsets() {
+        let original = mp4(false);
+        let written = write("after", &original);
+
+        assert_eq!(item(&written, b"\xa9nam", None), b"Title (Live)");
+        assert_eq!(item(&written, b"\xa9ART", None), b"One, Two");
+        assert_eq!(item(&written, b"\xa9day", None), b"2020-01-02");
+        assert_eq!(item(&written, b"trkn", None), [0, 0, 0, 3, 0, 12, 0, 0]);
+        assert_eq!(item(&written, b"disk", None), [0, 0, 0, 1, 0, 2]);
+        assert_eq!(item(&written, b"rtng", None), [1]);
+        assert_eq!(item(&written, b"----", Some("ISRC")), b"USABC2000001");
+        assert_eq!(item(&written, b"----", Some("TIDAL_TRACK_ID")), b"123");
+
+        // other user data is kept, and the old tags are replaced rather than added to
+        let udta = find_atom(&written, moov(&written).body(), &[b"udta"])
+            .unwrap()
+            .unwrap();
+        let kinds: Vec<[u8; 4]> = parse_atoms(&written, udta.body())
+            .unwrap()
+            .iter()
+            .map(|a| a.kind)
+            .collect();
+        assert_eq!(kinds, [*b"name", *b"meta"]);
+
+        // the mdat moved by the growth of moov, and both offset tables moved with it
+        let delta = (written.len() - original.len()) as u64;
+        for trak in 0..2 {
+            let before = read_offsets(&original, trak);
+            let after = read_offsets(&written, trak);
+            assert_eq!(after, [before[0] + delta, before[1] + delta]);
+            assert_eq!(&written[after[0] as usize..][..11], b"first chunk");
+            assert_eq!(&written[after[1] as usize..][..12], b"second chunk");
+        }
+        assert!(written.ends_with(SAMPLES));
+    }
+
+    #[test]
+    fn leaves_offsets_of_mdat_before_moov() {
+        let original = mp4(true);
+        let written = write("before", &original);
+        for trak in 0..2 {
+            let offsets = read_offsets(&written, trak);
+            assert_eq!(offsets, read_offsets(&original, trak));
+            assert_eq!(&written[offsets[0] as usize..][..11], b"first chunk");
+        }
+        assert_eq!(item(&written, b"\xa9alb", None), b"Album");
+    }
+}
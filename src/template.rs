@@ -0,0 +1,539 @@
+use anyhow::{anyhow, Error};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// A parsed path template.
+///
+/// Besides plain `{token}`s, a template can use:
+/// - `{token:02}` to zero pad a token to a width
+/// - `{token|lower}` to apply the `lower`, `upper`, `title` or `trim` filters, in order
+/// - `{token|fallback}` to use the text after `|` when the token is empty
+/// - `{?token}...{/}` and `{!token}...{/}` for text that is only kept when a token is set or empty
+/// - `{%if token > 1%}...{%else%}...{%endif%}` to compare a token with `==`, `!=`, `>`, `>=`,
+///   `<` or `<=`, or test whether it is set with `{%if token%}` and `{%if not token%}`
+///
+/// `{{` and `}}` are a literal `{` and `}`.
+#[derive(SerializeDisplay, DeserializeFromStr, Clone, Debug)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Token(Token),
+    Section {
+        condition: Condition,
+        then: Vec<Part>,
+        otherwise: Vec<Part>,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    name: String,
+    width: Option<usize>,
+    filters: Vec<Filter>,
+    fallback: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Filter {
+    Lower,
+    Upper,
+    Title,
+    Trim,
+}
+
+#[derive(Clone, Debug)]
+enum Condition {
+    Set(String),
+    Unset(String),
+    Compare(String, Op, String),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+// the pieces of a template before sections are matched up with their ends
+enum Lexeme {
+    Text(String),
+    Token(Token),
+    If(Condition),
+    Else,
+    EndIf,
+    Open(Condition),
+    Close,
+}
+
+impl Template {
+    pub fn is_empty(&self) -> bool {
+        self.source.is_empty()
+    }
+
+    /// Joins templates into one, with `separator` between each of them.
+    pub fn join<'a>(templates: impl IntoIterator<Item = &'a Template>, separator: &str) -> Self {
+        let mut joined = Template {
+            source: String::new(),
+            parts: Vec::new(),
+        };
+        for (i, template) in templates.into_iter().enumerate() {
+            if i > 0 {
+                joined.source.push_str(separator);
+                joined.parts.push(Part::Text(separator.to_string()));
+            }
+            joined.source.push_str(&template.source);
+            joined.parts.extend(template.parts.iter().cloned());
+        }
+        joined
+    }
+
+    /// Checks that the template only uses tokens out of `names`.
+    pub fn check(&self, names: &[&str]) -> Result<(), Error> {
+        match find_unknown(&self.parts, names) {
+            Some(name) => Err(anyhow!(
+                "Unknown token {{{name}}} in \"{}\", available tokens are {}",
+                self.source,
+                names.join(", ")
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Fills in the template, looking up the value of each token with `lookup`. Tokens it
+    /// doesn't know are empty.
+    pub fn render(&self, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+        let mut out = String::new();
+        render_parts(&self.parts, lookup, &mut out);
+        out
+    }
+}
+
+fn find_unknown<'a>(parts: &'a [Part], names: &[&str]) -> Option<&'a str> {
+    parts.iter().find_map(|part| match part {
+        Part::Text(_) => None,
+        Part::Token(token) => Some(token.name.as_str()).filter(|n| !names.contains(n)),
+        Part::Section {
+            condition,
+            then,
+            otherwise,
+        } => Some(condition.name())
+            .filter(|n| !names.contains(n))
+            .or_else(|| find_unknown(then, names))
+            .or_else(|| find_unknown(otherwise, names)),
+    })
+}
+
+fn render_parts(parts: &[Part], lookup: &dyn Fn(&str) -> Option<String>, out: &mut String) {
+    for part in parts {
+        match part {
+            Part::Text(text) => out.push_str(text),
+            Part::Token(token) => out.push_str(&token.render(lookup(&token.name))),
+            Part::Section {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let value = lookup(condition.name()).unwrap_or_default();
+                match condition.eval(&value) {
+                    true => render_parts(then, lookup, out),
+                    false => render_parts(otherwise, lookup, out),
+                }
+            }
+        }
+    }
+}
+
+impl Token {
+    fn parse(tag: &str) -> Result<Self, Error> {
+        let mut items = tag.split('|');
+        let head = items.next().unwrap_or_default();
+        let (name, spec) = match head.split_once(':') {
+            Some((name, spec)) => (name, Some(spec)),
+            None => (head, None),
+        };
+        let width = match spec {
+            Some(spec) => match spec.strip_prefix('0').map(usize::from_str) {
+                Some(Ok(width)) => Some(width),
+                _ => {
+                    return Err(anyhow!(
+                        "Unsupported format :{spec} in {{{tag}}}, only zero padding such as :02 is supported"
+                    ))
+                }
+            },
+            None => None,
+        };
+        let mut token = Token {
+            name: parse_name(name, tag)?,
+            width,
+            filters: Vec::new(),
+            fallback: None,
+        };
+        for item in items {
+            match Filter::from_str(item) {
+                Ok(filter) => token.filters.push(filter),
+                Err(_) if token.fallback.is_none() => token.fallback = Some(item.to_string()),
+                Err(_) => return Err(anyhow!("Token {{{tag}}} has more than one fallback")),
+            }
+        }
+        Ok(token)
+    }
+
+    fn render(&self, value: Option<String>) -> String {
+        let mut value = value.unwrap_or_default();
+        if value.is_empty() {
+            value = self.fallback.clone().unwrap_or_default();
+        }
+        if let Some(width) = self.width {
+            value = format!("{value:0>width$}");
+        }
+        for filter in &self.filters {
+            value = filter.apply(&value);
+        }
+        value
+    }
+}
+
+fn parse_name(name: &str, tag: &str) -> Result<String, Error> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    match valid {
+        true => Ok(name.to_string()),
+        false => Err(anyhow!("Invalid token name \"{name}\" in {{{tag}}}")),
+    }
+}
+
+impl Filter {
+    fn apply(self, value: &str) -> String {
+        match self {
+            Filter::Lower => value.to_lowercase(),
+            Filter::Upper => value.to_uppercase(),
+            Filter::Title => value
+                .split(' ')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) => first.to_uppercase().chain(chars).collect(),
+                        None => String::new(),
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(" "),
+            Filter::Trim => value.trim().to_string(),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+    fn from_str(input: &str) -> Result<Filter, Self::Err> {
+        match input {
+            "lower" => Ok(Filter::Lower),
+            "upper" => Ok(Filter::Upper),
+            "title" => Ok(Filter::Title),
+            "trim" => Ok(Filter::Trim),
+            _ => Err("Error".to_string()),
+        }
+    }
+}
+
+impl Condition {
+    fn parse(expr: &str, tag: &str) -> Result<Self, Error> {
+        // two character operators first, so >= isn't read as >
+        let ops = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            (">=", Op::Ge),
+            ("<=", Op::Le),
+            (">", Op::Gt),
+            ("<", Op::Lt),
+        ];
+        for (symbol, op) in ops {
+            if let Some((name, value)) = expr.split_once(symbol) {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                return Ok(Condition::Compare(
+                    parse_name(name.trim(), tag)?,
+                    op,
+                    value.to_string(),
+                ));
+            }
+        }
+        match expr.strip_prefix("not ") {
+            Some(name) => Ok(Condition::Unset(parse_name(name.trim(), tag)?)),
+            None => Ok(Condition::Set(parse_name(expr, tag)?)),
+        }
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Condition::Set(name) | Condition::Unset(name) | Condition::Compare(name, _, _) => name,
+        }
+    }
+
+    fn eval(&self, value: &str) -> bool {
+        match self {
+            Condition::Set(_) => !value.is_empty(),
+            Condition::Unset(_) => value.is_empty(),
+            Condition::Compare(_, op, other) => {
+                // numbers compare by value, anything else as text
+                let ordering = match (value.parse::<f64>(), other.parse::<f64>()) {
+                    (Ok(a), Ok(b)) => a.partial_cmp(&b),
+                    _ => Some(value.cmp(other.as_str())),
+                };
+                match (op, ordering) {
+                    (_, None) => false,
+                    (Op::Eq, Some(o)) => o == Ordering::Equal,
+                    (Op::Ne, Some(o)) => o != Ordering::Equal,
+                    (Op::Gt, Some(o)) => o == Ordering::Greater,
+                    (Op::Ge, Some(o)) => o != Ordering::Less,
+                    (Op::Lt, Some(o)) => o == Ordering::Less,
+                    (Op::Le, Some(o)) => o != Ordering::Greater,
+                }
+            }
+        }
+    }
+}
+
+fn lex(source: &str) -> Result<Vec<Lexeme>, Error> {
+    let mut lexemes = Vec::new();
+    let mut text = String::new();
+    let mut rest = source;
+    while let Some(i) = rest.find(['{', '}']) {
+        text.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if let Some(after) = tail.strip_prefix("{{").or_else(|| tail.strip_prefix("}}")) {
+            text.push_str(&tail[..1]);
+            rest = after;
+            continue;
+        }
+        if let Some(after) = tail.strip_prefix('}') {
+            text.push('}');
+            rest = after;
+            continue;
+        }
+        if !text.is_empty() {
+            lexemes.push(Lexeme::Text(std::mem::take(&mut text)));
+        }
+        if let Some(block) = tail.strip_prefix("{%") {
+            let end = block
+                .find("%}")
+                .ok_or_else(|| anyhow!("Unclosed {{% in \"{source}\""))?;
+            lexemes.push(lex_block(block[..end].trim())?);
+            rest = &block[end + 2..];
+        } else {
+            let end = tail
+                .find('}')
+                .ok_or_else(|| anyhow!("Unclosed {{ in \"{source}\""))?;
+            let tag = &tail[1..end];
+            let lexeme = match tag {
+                "/" => Lexeme::Close,
+                _ => match (tag.strip_prefix('?'), tag.strip_prefix('!')) {
+                    (Some(name), _) => Lexeme::Open(Condition::Set(parse_name(name, tag)?)),
+                    (_, Some(name)) => Lexeme::Open(Condition::Unset(parse_name(name, tag)?)),
+                    _ => Lexeme::Token(Token::parse(tag)?),
+                },
+            };
+            lexemes.push(lexeme);
+            rest = &tail[end + 1..];
+        }
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        lexemes.push(Lexeme::Text(text));
+    }
+    Ok(lexemes)
+}
+
+fn lex_block(block: &str) -> Result<Lexeme, Error> {
+    match block {
+        "else" => Ok(Lexeme::Else),
+        "endif" => Ok(Lexeme::EndIf),
+        _ => match block.strip_prefix("if ") {
+            Some(expr) => Ok(Lexeme::If(Condition::parse(expr.trim(), block)?)),
+            None => Err(anyhow!("Unknown block {{%{block}%}}")),
+        },
+    }
+}
+
+// a section that is still being parsed
+struct Frame {
+    condition: Condition,
+    block: bool,
+    then: Vec<Part>,
+    otherwise: Option<Vec<Part>>,
+}
+
+impl Frame {
+    fn new(condition: Condition, block: bool) -> Self {
+        Self {
+            condition,
+            block,
+            then: Vec::new(),
+            otherwise: None,
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+    fn from_str(source: &str) -> Result<Template, Self::Err> {
+        let mut root = Vec::new();
+        let mut stack: Vec<Frame> = Vec::new();
+        for lexeme in lex(source)? {
+            let part = match lexeme {
+                Lexeme::Text(text) => Part::Text(text),
+                Lexeme::Token(token) => Part::Token(token),
+                Lexeme::If(condition) => {
+                    stack.push(Frame::new(condition, true));
+                    continue;
+                }
+                Lexeme::Open(condition) => {
+                    stack.push(Frame::new(condition, false));
+                    continue;
+                }
+                Lexeme::Else => match stack.last_mut() {
+                    Some(frame) if frame.block && frame.otherwise.is_none() => {
+                        frame.otherwise = Some(Vec::new());
+                        continue;
+                    }
+                    _ => return Err(anyhow!("Unexpected {{%else%}} in \"{source}\"")),
+                },
+                Lexeme::EndIf | Lexeme::Close => {
+                    let block = matches!(lexeme, Lexeme::EndIf);
+                    match stack.pop() {
+                        Some(frame) if frame.block == block => Part::Section {
+                            condition: frame.condition,
+                            then: frame.then,
+                            otherwise: frame.otherwise.unwrap_or_default(),
+                        },
+                        _ => {
+                            let tag = if block { "{%endif%}" } else { "{/}" };
+                            return Err(anyhow!("Unexpected {tag} in \"{source}\""));
+                        }
+                    }
+                }
+            };
+            match stack.last_mut() {
+                Some(Frame {
+                    otherwise: Some(parts),
+                    ..
+                })
+                | Some(Frame { then: parts, .. }) => parts.push(part),
+                None => root.push(part),
+            }
+        }
+        if let Some(frame) = stack.last() {
+            let tag = if frame.block { "{%if%}" } else { "{?}" };
+            return Err(anyhow!(
+                "Unclosed {tag} section on {} in \"{source}\"",
+                frame.condition.name()
+            ));
+        }
+        Ok(Template {
+            source: source.to_string(),
+            parts: root,
+        })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, values: &[(&str, &str)]) -> String {
+        let template = Template::from_str(source).unwrap();
+        template.render(&|name| {
+            values
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.to_string())
+        })
+    }
+
+    fn error(source: &str) -> String {
+        Template::from_str(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn renders_tokens() {
+        let values = [("track_num", "7"), ("title", "  one more time ")];
+        assert_eq!(render("{track_num:02}", &values), "07");
+        assert_eq!(
+            render("{track_num:03} - {title|trim}", &values),
+            "007 - one more time"
+        );
+        assert_eq!(render("{title|trim|title}", &values), "One More Time");
+        assert_eq!(render("{title|trim|upper|lower}", &values), "one more time");
+        assert_eq!(render("{x|unknown}", &values), "unknown");
+        assert_eq!(render("{x|unknown|upper}", &values), "UNKNOWN");
+        assert_eq!(render("{track_num|unknown}", &values), "7");
+        assert_eq!(render("{{{track_num}}}", &values), "{7}");
+        assert_eq!(render("a}}b{{c", &values), "a}b{c");
+    }
+
+    #[test]
+    fn renders_sections() {
+        let set = [("x", "1")];
+        assert_eq!(render("a{?x} ({x}){/}b", &set), "a (1)b");
+        assert_eq!(render("a{?x} ({x}){/}b", &[]), "ab");
+        assert_eq!(render("a{!x} (none){/}b", &set), "ab");
+        assert_eq!(render("a{!x} (none){/}b", &[]), "a (none)b");
+
+        let source = "{%if a > 1%}disc {a}/{%else%}single/{%endif%}";
+        assert_eq!(render(source, &[("a", "2")]), "disc 2/");
+        assert_eq!(render(source, &[("a", "1")]), "single/");
+        assert_eq!(render(source, &[]), "single/");
+        assert_eq!(render("{%if a > 1%}many{%endif%}", &[("a", "10")]), "many");
+        assert_eq!(
+            render("{%if t == \"EP\"%}ep{%endif%}", &[("t", "EP")]),
+            "ep"
+        );
+        assert_eq!(render("{%if not a%}none{%endif%}", &[]), "none");
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        assert!(error("{title").starts_with("Unclosed {"));
+        assert!(error("title{/}").starts_with("Unexpected {/}"));
+        assert!(error("title{%else%}").starts_with("Unexpected {%else%}"));
+        assert_eq!(
+            error("{title|unknown|none}"),
+            "Token {title|unknown|none} has more than one fallback"
+        );
+        assert!(error("{?x}open").starts_with("Unclosed {?} section on x"));
+        assert!(error("{title:>5}").starts_with("Unsupported format :>5"));
+    }
+
+    #[test]
+    fn checks_token_names() {
+        let template = Template::from_str("{artist}/{?year}{year} - {/}{album}").unwrap();
+        assert!(template.check(&["artist", "album", "year"]).is_ok());
+        let err = template.check(&["artist", "year"]).unwrap_err();
+        assert!(err.to_string().starts_with("Unknown token {album}"));
+
+        let template = Template::from_str("{%if disc > 1%}{disc}{%endif%}").unwrap();
+        assert!(template.check(&[]).is_err());
+    }
+}
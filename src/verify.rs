@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Error};
+use claxon::FlacReader;
+use md5::{Digest, Md5};
+use std::path::Path;
+
+/// Decodes the whole FLAC file and compares the decoded audio against the
+/// MD5 signature and sample count stored in its STREAMINFO block.
+pub fn verify_flac(path: &Path) -> Result<(), Error> {
+    let mut reader = FlacReader::open(path)?;
+    let info = reader.streaminfo();
+    // the signature covers the samples as little endian integers of the smallest byte width
+    let sample_bytes = info.bits_per_sample.div_ceil(8) as usize;
+
+    let mut hasher = Md5::new();
+    let mut decoded: u64 = 0;
+    let mut frame = Vec::new();
+    let mut blocks = reader.blocks();
+    let mut buffer = Vec::new();
+    while let Some(block) = blocks.read_next_or_eof(buffer)? {
+        frame.clear();
+        for i in 0..block.duration() {
+            for ch in 0..block.channels() {
+                let sample = block.sample(ch, i).to_le_bytes();
+                frame.extend_from_slice(&sample[..sample_bytes]);
+            }
+        }
+        hasher.update(&frame);
+        decoded += u64::from(block.duration());
+        buffer = block.into_buffer();
+    }
+
+    if let Some(samples) = info.samples {
+        if samples != decoded {
+            return Err(anyhow!(
+                "Decoded {decoded} samples, expected {samples} from STREAMINFO"
+            ));
+        }
+    }
+    // an all zero signature means the encoder didn't compute one
+    if info.md5sum != [0; 16] && hasher.finalize()[..] != info.md5sum {
+        return Err(anyhow!("Decoded audio does not match the STREAMINFO MD5"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: usize = 16;
+
+    fn crc8(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |mut crc, byte| {
+            crc ^= byte;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 {
+                    (crc << 1) ^ 0x07
+                } else {
+                    crc << 1
+                };
+            }
+            crc
+        })
+    }
+
+    fn crc16(data: &[u8]) -> u16 {
+        data.iter().fold(0u16, |mut crc, byte| {
+            crc ^= u16::from(*byte) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x8005
+                } else {
+                    crc << 1
+                };
+            }
+            crc
+        })
+    }
+
+    // a mono 16 bit 44.1 kHz frame of 16 samples, stored verbatim
+    fn frame(number: u8, samples: &[i16]) -> Vec<u8> {
+        let mut frame = vec![0xff, 0xf8, 0x69, 0x08, number, BLOCK as u8 - 1];
+        frame.push(crc8(&frame));
+        frame.push(0x02);
+        for sample in samples {
+            frame.extend_from_slice(&sample.to_be_bytes());
+        }
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame
+    }
+
+    // a FLAC file of `samples`, whose STREAMINFO claims `total` samples and signature `md5`
+    fn flac(samples: &[i16], total: u64, md5: [u8; 16]) -> Vec<u8> {
+        let mut file = b"fLaC".to_vec();
+        file.extend_from_slice(&[0x80, 0, 0, 34]);
+        file.extend_from_slice(&(BLOCK as u16).to_be_bytes());
+        file.extend_from_slice(&(BLOCK as u16).to_be_bytes());
+        file.extend_from_slice(&[0; 6]);
+        let packed = (44_100u64 << 44) | (15 << 36) | total;
+        file.extend_from_slice(&packed.to_be_bytes());
+        file.extend_from_slice(&md5);
+        for (i, block) in samples.chunks(BLOCK).enumerate() {
+            file.extend(frame(i as u8, block));
+        }
+        file
+    }
+
+    fn samples() -> Vec<i16> {
+        (0..BLOCK as i16 * 3).map(|i| i * 300 - 7000).collect()
+    }
+
+    fn signature(samples: &[i16]) -> [u8; 16] {
+        let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        Md5::digest(bytes).into()
+    }
+
+    fn verify(name: &str, file: &[u8]) -> Result<(), Error> {
+        let path =
+            std::env::temp_dir().join(format!("tdl-verify-{}-{name}.flac", std::process::id()));
+        std::fs::write(&path, file).unwrap();
+        let result = verify_flac(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn passes_a_matching_signature() {
+        let samples = samples();
+        let file = flac(&samples, samples.len() as u64, signature(&samples));
+        verify("match", &file).unwrap();
+        // files without a signature are only checked for their sample count
+        verify("unsigned", &flac(&samples, samples.len() as u64, [0; 16])).unwrap();
+    }
+
+    #[test]
+    fn fails_a_mismatched_signature() {
+        let samples = samples();
+        let mut signature = signature(&samples);
+        signature[0] ^= 1;
+        let err = verify("mismatch", &flac(&samples, samples.len() as u64, signature));
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "Decoded audio does not match the STREAMINFO MD5"
+        );
+    }
+
+    #[test]
+    fn fails_a_short_file() {
+        let samples = samples();
+        let file = flac(&samples, samples.len() as u64, signature(&samples));
+        // a frame missing at the end
+        let short = &file[..file.len() - frame(2, &samples[2 * BLOCK..]).len()];
+        let err = verify("missing-frame", short).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Decoded 32 samples, expected 48 from STREAMINFO"
+        );
+        // a frame cut off halfway
+        assert!(verify("cut", &file[..file.len() - 10]).is_err());
+    }
+}